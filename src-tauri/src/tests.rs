@@ -0,0 +1,3721 @@
+use super::*;
+use std::fs;
+use std::path::PathBuf;
+
+// ---------- 测试辅助函数 ----------
+
+// 在系统临时目录下创建一个空目录，每个测试使用不同的名字，避免并行测试互相影响
+fn temp_project(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join("vibesnap-tests").join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// 执行真实的 git 命令并返回标准输出，用于准备测试仓库
+fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git").args(args).current_dir(dir).output().unwrap();
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+// 创建一个已配置提交用户信息的空仓库
+fn init_repo(name: &str) -> PathBuf {
+    let dir = temp_project(name);
+    git(&dir, &["init", "-q", "-b", "master"]);
+    git(&dir, &["config", "user.name", "tester"]);
+    git(&dir, &["config", "user.email", "tester@example.com"]);
+    git(&dir, &["config", "commit.gpgsign", "false"]);
+    dir
+}
+
+// 创建包含 count 个提交的仓库，第 i 个提交把 file.txt 写为 "v{i}"
+fn repo_with_commits(name: &str, count: usize) -> PathBuf {
+    let dir = init_repo(name);
+    for i in 1..=count {
+        fs::write(dir.join("file.txt"), format!("v{}", i)).unwrap();
+        git(&dir, &["add", "."]);
+        git(&dir, &["commit", "-qm", &format!("commit {}", i)]);
+    }
+    dir
+}
+
+fn path_string(dir: &Path) -> String {
+    dir.to_string_lossy().to_string()
+}
+
+// 只带 .git 目录的项目，所有 git 命令都由模拟 git 响应
+fn scripted_project(name: &str) -> PathBuf {
+    let dir = temp_project(name);
+    fs::create_dir_all(dir.join(".git")).unwrap();
+    dir
+}
+
+fn watcher_config(dir: &Path) -> FileWatcherConfig {
+    FileWatcherConfig {
+        project_path: path_string(dir),
+        log_file_path: None,
+        log_sources: vec![],
+        debounce_duration: 0,
+        debounce_overrides: vec![],
+        max_wait: None,
+        user_idle_duration: None,
+        periodic_interval_ms: None,
+        log_lines_to_use: None,
+        message_style: "vibe".to_string(),
+        watch_git_index: false,
+        include_untracked: true,
+        max_file_size_mb: None,
+        large_file_ignore: "none".to_string(),
+        bypass_hooks: false,
+        save_trigger_file: None,
+        bot_identity: None,
+        signing_fallback: "error".to_string(),
+        auto_push: false,
+        encoding_check: false,
+        enforce_no_secrets: false,
+        junk_patterns: vec![],
+    }
+}
+
+fn git_ok(stdout: &str) -> ScriptedResponse {
+    ScriptedResponse::Output { code: 0, stdout: stdout.to_string(), stderr: String::new() }
+}
+
+fn git_fail(code: i32, stderr: &str) -> ScriptedResponse {
+    ScriptedResponse::Output { code, stdout: String::new(), stderr: stderr.to_string() }
+}
+
+fn git_timeout() -> ScriptedResponse {
+    ScriptedResponse::Error(std::io::ErrorKind::TimedOut, "git 执行超时".to_string())
+}
+
+// 模拟 git，没有预设响应的命令都成功且没有输出；通过参数传给被测函数
+fn scripted_git() -> Arc<ScriptedGitRunner> {
+    let runner = Arc::new(ScriptedGitRunner::new());
+    runner.respond_default(git_ok(""));
+    runner
+}
+
+const FAKE_HEAD: &str = "1111111111111111111111111111111111111111";
+const FAKE_TARGET: &str = "2222222222222222222222222222222222222222";
+const MISSING_IDENTITY: &str = "Author identity unknown\n\n*** Please tell me who you are.\n\nRun\n\n  git config --global user.email \"you@example.com\"\n";
+
+// ---------- 模拟 git (GitRunner) ----------
+
+#[tokio::test]
+async fn scripted_create_snapshot_success() {
+    let dir = scripted_project("scripted-create-success");
+    let runner = scripted_git();
+    runner
+        .respond(&["rev-parse", "--verify", "--quiet"], git_ok(&format!("{}\n", FAKE_HEAD)))
+        .respond(&["diff", "--cached", "--quiet"], git_fail(1, ""))
+        .respond(&["commit"], git_ok("[master 3333333] [Vibe] AI Prompt: 添加登录页\n"));
+
+    let result = create_snapshot_with_options(runner.clone(), path_string(&dir), "添加登录页".to_string(), SnapshotOptions::default(), None)
+        .await
+        .unwrap();
+
+    assert!(result.success, "{:?}", result.error);
+    let calls = runner.calls();
+    assert!(calls.iter().any(|call| call.args.first().map(String::as_str) == Some("add")));
+    let commit = calls.iter().find(|call| call.args.first().map(String::as_str) == Some("commit")).unwrap();
+    assert!(commit.args.iter().any(|arg| arg.contains("添加登录页")));
+}
+
+#[tokio::test]
+async fn scripted_create_snapshot_nothing_to_commit() {
+    let dir = scripted_project("scripted-create-nothing");
+    let runner = scripted_git();
+    // diff --cached --quiet 返回 0 表示暂存区没有修改
+    runner.respond(&["diff", "--cached", "--quiet"], git_ok(""));
+
+    let result = create_snapshot_with_options(runner.clone(), path_string(&dir), "没有修改".to_string(), SnapshotOptions::default(), None)
+        .await
+        .unwrap();
+
+    assert!(!result.success);
+    assert!(result.no_changes);
+    assert_eq!(result.message, "没有检测到变更");
+    assert!(!runner.calls().iter().any(|call| call.args.first().map(String::as_str) == Some("commit")));
+}
+
+#[tokio::test]
+async fn scripted_create_snapshot_identity_missing() {
+    let dir = scripted_project("scripted-create-identity");
+    let runner = scripted_git();
+    runner
+        .respond(&["diff", "--cached", "--quiet"], git_fail(1, ""))
+        .respond(&["commit"], git_fail(128, MISSING_IDENTITY));
+
+    let result = create_snapshot_with_options(runner.clone(), path_string(&dir), "提交".to_string(), SnapshotOptions::default(), None)
+        .await
+        .unwrap();
+
+    assert!(!result.success);
+    assert!(result.error.unwrap().starts_with("Git 用户信息未配置"));
+}
+
+#[tokio::test]
+async fn scripted_create_snapshot_timeout() {
+    let dir = scripted_project("scripted-create-timeout");
+    let runner = scripted_git();
+    runner.respond(&["add"], git_timeout());
+
+    let result = create_snapshot_with_options(runner.clone(), path_string(&dir), "提交".to_string(), SnapshotOptions::default(), None)
+        .await
+        .unwrap();
+
+    assert!(!result.success);
+    assert_eq!(result.message, "添加文件失败");
+    assert!(result.error.unwrap().contains("git 执行超时"));
+}
+
+#[tokio::test]
+async fn scripted_create_snapshot_malformed_output() {
+    let dir = scripted_project("scripted-create-malformed");
+    let runner = scripted_git();
+    // 无法解析的输出不应导致崩溃，提交本身成功即视为成功
+    runner
+        .respond(&["rev-parse"], git_ok("\u{0}\u{1}不是哈希"))
+        .respond(&["diff", "--cached", "--quiet"], git_fail(1, ""))
+        .respond(&["diff"], git_ok("@@ 损坏的差异 @@\n+++\n"))
+        .respond(&["commit"], git_ok("\u{fffd}\u{fffd}"));
+
+    let result = create_snapshot_with_options(runner.clone(), path_string(&dir), "提交".to_string(), SnapshotOptions::default(), None)
+        .await
+        .unwrap();
+
+    assert!(result.success, "{:?}", result.error);
+}
+
+#[tokio::test]
+async fn scripted_rollback_success() {
+    let dir = scripted_project("scripted-rollback-success");
+    let runner = scripted_git();
+    runner
+        .respond(&["rev-parse", "--verify", "--quiet"], git_ok(&format!("{}\n", FAKE_TARGET)))
+        .respond(&["branch", "--show-current"], git_ok("master\n"));
+
+    let result = rollback_to_commit(runner.as_ref(), path_string(&dir), "2222222".to_string()).await.unwrap();
+
+    assert!(result.success, "{:?}", result.error);
+    assert!(!result.is_detached_head);
+    let reset = runner.calls().into_iter().find(|call| call.args.first().map(String::as_str) == Some("reset")).unwrap();
+    assert_eq!(reset.args, vec!["reset", "--hard", FAKE_TARGET]);
+}
+
+#[tokio::test]
+async fn scripted_rollback_unknown_revision() {
+    let dir = scripted_project("scripted-rollback-unknown");
+    let runner = scripted_git();
+    runner.respond(&["rev-parse"], git_fail(1, ""));
+
+    let result = rollback_to_commit(runner.as_ref(), path_string(&dir), "deadbeef".to_string()).await.unwrap();
+
+    assert!(!result.success);
+    assert!(!runner.calls().iter().any(|call| call.args.first().map(String::as_str) == Some("reset")));
+}
+
+#[tokio::test]
+async fn scripted_rollback_timeout() {
+    let dir = scripted_project("scripted-rollback-timeout");
+    let runner = scripted_git();
+    runner
+        .respond(&["rev-parse", "--verify", "--quiet"], git_ok(&format!("{}\n", FAKE_TARGET)))
+        .respond(&["reset"], git_timeout());
+
+    let result = rollback_to_commit(runner.as_ref(), path_string(&dir), FAKE_TARGET.to_string()).await.unwrap();
+
+    assert!(!result.success);
+    assert!(result.error.unwrap().contains("git 执行超时"));
+}
+
+#[tokio::test]
+async fn scripted_rollback_malformed_output() {
+    let dir = scripted_project("scripted-rollback-malformed");
+    let runner = scripted_git();
+    // rev-parse 成功但没有输出哈希时不能执行 reset
+    runner.respond(&["rev-parse"], git_ok("\n"));
+
+    let result = rollback_to_commit(runner.as_ref(), path_string(&dir), "HEAD~1".to_string()).await.unwrap();
+
+    assert!(!result.success);
+    assert!(!runner.calls().iter().any(|call| call.args.first().map(String::as_str) == Some("reset")));
+}
+
+#[tokio::test]
+async fn scripted_history_success() {
+    let dir = scripted_project("scripted-history-success");
+    let runner = scripted_git();
+    runner.respond(
+        &["log"],
+        git_ok(&format!("{}|{}|2024-01-01 10:00:00 +0000||[Vibe] AI Prompt: 添加登录页\n", &FAKE_HEAD[..7], FAKE_HEAD)),
+    );
+
+    let history = snapshot_history(runner.as_ref(), path_string(&dir), None, None, None, None, None).await.unwrap();
+
+    assert!(history.success, "{:?}", history.error);
+    assert_eq!(history.history.len(), 1);
+    assert_eq!(history.history[0].prompt.as_deref(), Some("添加登录页"));
+}
+
+#[tokio::test]
+async fn scripted_history_malformed_output() {
+    let dir = scripted_project("scripted-history-malformed");
+    let runner = scripted_git();
+    runner.respond(
+        &["log"],
+        git_ok(&format!("{}|{}|2024-01-01 10:00:00 +0000||[Vibe] AI Prompt: 有效\n无法解析的行\n||\n", &FAKE_HEAD[..7], FAKE_HEAD)),
+    );
+
+    let history = snapshot_history(runner.as_ref(), path_string(&dir), None, None, None, None, None).await.unwrap();
+
+    assert_eq!(history.history.len(), 1);
+    assert_eq!(history.history[0].prompt.as_deref(), Some("有效"));
+}
+
+#[tokio::test]
+async fn scripted_history_timeout() {
+    let dir = scripted_project("scripted-history-timeout");
+    let runner = scripted_git();
+    runner.respond(&["log"], git_timeout());
+
+    let history = snapshot_history(runner.as_ref(), path_string(&dir), None, None, None, None, None).await.unwrap();
+
+    assert!(!history.success);
+    assert!(history.error.unwrap().contains("git 执行超时"));
+}
+
+#[tokio::test]
+async fn scripted_auto_commit_success() {
+    let dir = scripted_project("scripted-auto-success");
+    let runner = scripted_git();
+    runner
+        .respond(&["diff", "--cached", "--quiet"], git_fail(1, ""))
+        .respond(&["commit"], git_ok("[master 3333333] 自动快照\n"));
+
+    let result = auto_commit_changes(runner.as_ref(), &watcher_config(&dir)).await.unwrap();
+
+    assert!(result.success, "{:?}", result.error);
+    assert!(runner.calls().iter().any(|call| call.args.first().map(String::as_str) == Some("commit")));
+}
+
+#[tokio::test]
+async fn scripted_auto_commit_nothing_to_commit() {
+    let dir = scripted_project("scripted-auto-nothing");
+    let runner = scripted_git();
+    runner.respond(&["diff", "--cached", "--quiet"], git_ok(""));
+
+    let result = auto_commit_changes(runner.as_ref(), &watcher_config(&dir)).await.unwrap();
+
+    assert!(!result.success);
+    assert!(result.no_changes);
+    assert_eq!(result.message, "没有检测到变更");
+}
+
+#[tokio::test]
+async fn scripted_auto_commit_identity_missing() {
+    let dir = scripted_project("scripted-auto-identity");
+    let runner = scripted_git();
+    runner
+        .respond(&["diff", "--cached", "--quiet"], git_fail(1, ""))
+        .respond(&["commit"], git_fail(128, MISSING_IDENTITY));
+
+    let result = auto_commit_changes(runner.as_ref(), &watcher_config(&dir)).await.unwrap();
+
+    assert!(!result.success);
+    assert!(result.error.unwrap().starts_with("Git 用户信息未配置"));
+}
+
+#[tokio::test]
+async fn scripted_auto_commit_timeout() {
+    let dir = scripted_project("scripted-auto-timeout");
+    let runner = scripted_git();
+    runner.respond(&["add"], git_timeout());
+
+    let result = auto_commit_changes(runner.as_ref(), &watcher_config(&dir)).await.unwrap();
+
+    assert!(!result.success);
+    assert!(result.error.unwrap_or(result.message).contains("git 执行超时"));
+}
+
+#[tokio::test]
+async fn scripted_auto_commit_malformed_output() {
+    let dir = scripted_project("scripted-auto-malformed");
+    let runner = scripted_git();
+    runner
+        .respond(&["status"], git_ok("?? \u{0}损坏\u{0}\u{0}"))
+        .respond(&["diff", "--cached", "--quiet"], git_fail(1, ""))
+        .respond(&["diff"], git_ok("diff --git\n@@ @@\n"))
+        .respond(&["commit"], git_ok(""));
+
+    let result = auto_commit_changes(runner.as_ref(), &watcher_config(&dir)).await.unwrap();
+
+    assert!(result.success, "{:?}", result.error);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn scripted_git_is_used_from_spawned_tasks() {
+    let dir = scripted_project("scripted-auto-spawned");
+    let runner = scripted_git();
+    runner
+        .respond(&["diff", "--cached", "--quiet"], git_fail(1, ""))
+        .respond(&["commit"], git_ok("[master 3333333] 自动快照\n"));
+    let git: SharedGitRunner = runner.clone();
+    let config = watcher_config(&dir);
+
+    // 监听器在其他工作线程上的任务中提交，仍然使用传入的 GitRunner
+    let result = tokio::spawn(async move { auto_commit_changes(git.as_ref(), &config).await })
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert!(result.success, "{:?}", result.error);
+    assert!(runner.calls().iter().any(|call| call.args.first().map(String::as_str) == Some("commit")));
+}
+
+#[cfg(unix)]
+#[test]
+fn git_invocation_keeps_non_utf8_arguments() {
+    use std::os::unix::ffi::OsStrExt;
+    let name = std::ffi::OsStr::from_bytes(b"caf\xe9.txt");
+    let dir = temp_project("non-utf8-args");
+    let mut command = Command::new("git");
+    command.arg("add").arg("--").arg(name).current_dir(&dir);
+
+    let invocation = prepare_git_invocation(&mut command);
+
+    assert_eq!(invocation.raw_args[2].as_os_str(), name);
+    assert_eq!(invocation.raw_dir.as_deref(), Some(dir.as_path()));
+    let rebuilt = system_git_command(&invocation);
+    assert_eq!(rebuilt.get_args().nth(2), Some(name));
+}
+
+#[cfg(unix)]
+#[test]
+fn run_git_passes_non_utf8_paths_to_git() {
+    use std::os::unix::ffi::OsStrExt;
+    let dir = init_repo("non-utf8-paths");
+    let name = std::ffi::OsStr::from_bytes(b"caf\xe9.txt");
+    if fs::write(dir.join(name), "x").is_err() {
+        // 文件系统不允许非 UTF-8 文件名时跳过
+        return;
+    }
+
+    let output = run_git(Command::new("git").arg("add").arg("--").arg(name).current_dir(&dir)).unwrap();
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let staged = Command::new("git").args(["diff", "--cached", "--name-only", "-z"]).current_dir(&dir).output().unwrap();
+    assert_eq!(staged.stdout, [name.as_bytes(), b"\0"].concat());
+}
+
+#[cfg(unix)]
+#[test]
+fn git_locale_keeps_character_set_for_case_insensitive_grep() {
+    let dir = init_repo("locale-grep");
+    fs::write(dir.join("fruit.txt"), "Äpfel\n").unwrap();
+    git(&dir, &["add", "."]);
+    git(&dir, &["commit", "-qm", "init"]);
+
+    // 模拟用户环境使用 UTF-8 字符集，提示信息仍固定为英文
+    let mut command = Command::new("git");
+    command.args(["grep", "-n", "-F", "-i", "-e", "äpfel", "HEAD", "--"]).current_dir(&dir).env("LC_CTYPE", "C.UTF-8");
+    let invocation = prepare_git_invocation(&mut command);
+    assert!(invocation.raw_env_set.iter().any(|(key, value)| key == "LC_MESSAGES" && value == "C"));
+    assert!(!invocation.raw_env_set.iter().any(|(key, _)| key == "LC_ALL"));
+
+    let output = run_git(&mut command).unwrap();
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Äpfel"));
+}
+
+#[test]
+fn scripted_git_receives_stdin() {
+    let runner = scripted_git();
+    runner.respond(&["hash-object"], git_ok("0123456789abcdef0123456789abcdef01234567\n"));
+
+    let output = run_git_with_input_via(runner.as_ref(), Command::new("git").arg("hash-object").arg("--stdin"), b"content").unwrap();
+
+    assert!(output.status.success());
+    let calls = runner.calls();
+    assert_eq!(calls.len(), 1);
+    assert_eq!(calls[0].stdin.as_deref(), Some(&b"content"[..]));
+}
+
+#[test]
+fn system_git_writes_stdin() {
+    let dir = init_repo("system-git-stdin");
+    fs::write(dir.join("content.txt"), "content").unwrap();
+
+    let output = run_git_with_input(Command::new("git").arg("hash-object").arg("--stdin").current_dir(&dir), b"content").unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), git(&dir, &["hash-object", "content.txt"]));
+}
+
+// ---------- 没有变更的结果与停止监听 ----------
+
+#[tokio::test]
+async fn snapshot_reports_no_changes_flag() {
+    let dir = init_repo("no-changes-flag");
+    fs::write(dir.join("a.txt"), "1").unwrap();
+    let first = create_snapshot_with_options(system_git(), path_string(&dir), "第一次".to_string(), SnapshotOptions::default(), None)
+        .await
+        .unwrap();
+    assert!(first.success && !first.no_changes, "{:?}", first.error);
+
+    let second = create_snapshot_with_options(system_git(), path_string(&dir), "第二次".to_string(), SnapshotOptions::default(), None)
+        .await
+        .unwrap();
+    assert!(!second.success);
+    assert!(second.no_changes);
+
+    match auto_commit_event(&path_string(&dir), &Ok(second)).await {
+        WatcherEventKind::CommitSkipped { reason } => assert_eq!(reason, "no_changes"),
+        _ => panic!("没有变更时应报告 CommitSkipped"),
+    }
+}
+
+fn register_test_session(project_path: &str) -> (Arc<Mutex<DebounceWindow>>, tokio::sync::watch::Receiver<bool>, Arc<tokio::sync::watch::Sender<bool>>) {
+    let window = Arc::new(Mutex::new(DebounceWindow {
+        active: false,
+        started_at: Instant::now(),
+        deadline: Instant::now(),
+        changed_paths: HashSet::new(),
+        active_override: None,
+        cancel_count: 0,
+    }));
+    let (stop_tx, stop_rx) = tokio::sync::watch::channel(false);
+    let stop_tx = Arc::new(stop_tx);
+    WATCHER_SESSIONS.get_or_init(|| Mutex::new(HashMap::new())).lock().unwrap().insert(
+        project_path.to_string(),
+        WatcherSession {
+            window: Arc::downgrade(&window),
+            commit_lock: Arc::new(tokio::sync::Mutex::new(())),
+            stop: stop_tx.clone(),
+            config: watcher_config(Path::new(project_path)),
+        },
+    );
+    (window, stop_rx, stop_tx)
+}
+
+#[tokio::test]
+async fn stop_file_watcher_stops_only_the_given_project() {
+    let (_window_a, stop_a, _) = register_test_session("/stop-test/a");
+    let (_window_b, stop_b, _) = register_test_session("/stop-test/b");
+
+    let status = stop_file_watcher(Some("/stop-test/a".to_string())).await.unwrap();
+
+    assert!(!status.is_watching);
+    assert!(status.warning.is_none());
+    assert!(*stop_a.borrow());
+    assert!(!*stop_b.borrow());
+    assert!(watcher_session("/stop-test/a").is_none());
+    assert!(watcher_session("/stop-test/b").is_some());
+
+    let again = stop_file_watcher(Some("/stop-test/a".to_string())).await.unwrap();
+    assert!(again.warning.is_some());
+    stop_watcher_sessions(Some("/stop-test/b"));
+}
+
+#[test]
+fn finished_watcher_keeps_newer_session() {
+    let (_old_window, _, old_stop) = register_test_session("/stop-test/restart");
+    let (_new_window, new_stop_rx, _) = register_test_session("/stop-test/restart");
+
+    // 旧的监听任务结束时不能移除同一项目重新启动的会话
+    remove_watcher_session("/stop-test/restart", &old_stop);
+
+    assert!(watcher_session("/stop-test/restart").is_some());
+    stop_watcher_sessions(Some("/stop-test/restart"));
+    assert!(*new_stop_rx.borrow());
+}
+
+// ---------- create_snapshot_if_changed ----------
+
+#[tokio::test]
+async fn snapshot_if_changed_is_idempotent() {
+    let dir = init_repo("snapshot-if-changed");
+    fs::write(dir.join("a.txt"), "1").unwrap();
+
+    let first = snapshot_if_changed(path_string(&dir), "修改 a".to_string(), None).await.unwrap();
+    assert!(first.success && !first.no_changes, "{:?}", first.error);
+    assert_eq!(git(&dir, &["rev-list", "--count", "HEAD"]).trim(), "1");
+
+    let second = snapshot_if_changed(path_string(&dir), "修改 a".to_string(), None).await.unwrap();
+    assert!(second.success && second.no_changes);
+    assert_eq!(git(&dir, &["rev-list", "--count", "HEAD"]).trim(), "1");
+}
+
+#[tokio::test]
+async fn snapshot_if_changed_treats_ignored_changes_as_unchanged() {
+    let dir = init_repo("snapshot-if-changed-ignored");
+    fs::write(dir.join("a.txt"), "1").unwrap();
+    snapshot_if_changed(path_string(&dir), "初始".to_string(), None).await.unwrap();
+
+    // 只修改了 assume-unchanged 的文件：status 为空，不会调用 create_snapshot
+    git(&dir, &["update-index", "--assume-unchanged", "a.txt"]);
+    fs::write(dir.join("a.txt"), "2").unwrap();
+    let result = snapshot_if_changed(path_string(&dir), "再次".to_string(), None).await.unwrap();
+    assert!(result.success && result.no_changes);
+}
+
+#[tokio::test]
+async fn snapshot_if_changed_reports_invalid_project() {
+    let dir = temp_project("snapshot-if-changed-not-repo");
+    let result = snapshot_if_changed(path_string(&dir), "提交".to_string(), None).await.unwrap();
+    assert!(!result.success && !result.no_changes);
+    assert_eq!(result.message, "项目不是 Git 仓库");
+}
+
+// ---------- 日期格式 ----------
+
+#[test]
+fn format_git_date_follows_locale_and_format() {
+    let raw = "2024-03-05 14:07:00 +0800";
+    let local = DateTime::parse_from_str(raw, "%Y-%m-%d %H:%M:%S %z").unwrap().with_timezone(&Local);
+
+    assert_eq!(format_git_date(raw, "zh-CN", "friendly"), local.format("%Y年%m月%d日 %H:%M").to_string());
+    assert_eq!(format_git_date(raw, "en-US", "friendly"), local.format("%b %-d, %Y %H:%M").to_string());
+    assert_eq!(format_git_date(raw, "zh-CN", "short"), local.format("%m月%d日 %H:%M").to_string());
+    assert_eq!(format_git_date(raw, "EN", "short"), local.format("%b %-d %H:%M").to_string());
+    assert_eq!(format_git_date(raw, "en", "iso"), local.to_rfc3339_opts(SecondsFormat::Secs, false));
+}
+
+#[test]
+fn format_git_date_accepts_strict_iso_and_keeps_unparseable_input() {
+    let iso = "2024-03-05T06:07:00Z";
+    let local = DateTime::parse_from_rfc3339(iso).unwrap().with_timezone(&Local);
+    assert_eq!(format_git_date(iso, "zh-CN", "friendly"), local.format("%Y年%m月%d日 %H:%M").to_string());
+    assert_eq!(git_date_to_rfc3339(" 2024-03-05 14:07:00 +0800 "), "2024-03-05T14:07:00+08:00");
+
+    assert_eq!(format_git_date("  不是日期 ", "en", "iso"), "不是日期");
+    assert_eq!(git_date_to_rfc3339("not a date"), "not a date");
+}
+
+#[test]
+fn format_git_date_handles_utc_and_negative_offsets() {
+    // 两个日期是同一时刻：格式化后的本地时间相同，RFC3339 值保留各自的时区偏移
+    let utc = "2024-03-06 03:30:00 +0000";
+    let eastern = "2024-03-05 22:30:00 -0500";
+    let local = DateTime::parse_from_rfc3339("2024-03-06T03:30:00Z").unwrap().with_timezone(&Local);
+
+    for raw in [utc, eastern] {
+        assert_eq!(format_git_date(raw, "zh-CN", "friendly"), local.format("%Y年%m月%d日 %H:%M").to_string());
+        assert_eq!(format_git_date(raw, "en-US", "short"), local.format("%b %-d %H:%M").to_string());
+        assert_eq!(format_git_date(raw, "en", "iso"), local.to_rfc3339_opts(SecondsFormat::Secs, false));
+    }
+    assert_eq!(git_date_to_rfc3339(utc), "2024-03-06T03:30:00+00:00");
+    assert_eq!(git_date_to_rfc3339(eastern), "2024-03-05T22:30:00-05:00");
+    assert_eq!(git_date_to_rfc3339("2024-03-05T22:30:00-05:00"), "2024-03-05T22:30:00-05:00");
+}
+
+#[tokio::test]
+async fn date_display_settings_persist_and_apply_to_history() {
+    let _guard = APP_CONFIG_TEST_LOCK.lock().await;
+    let dir = repo_with_commits("date_display_settings", 1);
+    let config_file = dir.join("config").join(APP_CONFIG_FILE);
+    init_app_config(config_file.clone());
+
+    assert!(set_date_display_settings("en-US".to_string(), "weekday".to_string()).await.is_err());
+    set_date_display_settings("en-US".to_string(), "iso".to_string()).await.unwrap();
+
+    // 模拟重新启动后仍使用保存的设置
+    *DATE_DISPLAY_SETTINGS.lock().unwrap() = None;
+    init_app_config(config_file);
+    let saved = get_date_display_settings().await.unwrap();
+    assert_eq!(saved.locale, "en-US");
+    assert_eq!(saved.date_format, "iso");
+
+    let history = get_snapshot_history(path_string(&dir), None, None, None, None, None).await.unwrap();
+    assert!(history.success, "{:?}", history.error);
+    let item = &history.history[0];
+    let expected = DateTime::parse_from_rfc3339(&item.raw_date).unwrap().with_timezone(&Local);
+    assert_eq!(item.date, expected.to_rfc3339_opts(SecondsFormat::Secs, false));
+
+    // 增量刷新使用同一份设置
+    let known = git(&dir, &["rev-parse", "HEAD"]).trim().to_string();
+    fs::write(dir.join("file.txt"), "incremental").unwrap();
+    git(&dir, &["commit", "-qam", "incremental"]);
+    let since = get_history_since(path_string(&dir), known).await.unwrap();
+    assert!(since.success, "{:?}", since.error);
+    let item = &since.history[0];
+    let expected = DateTime::parse_from_rfc3339(&item.raw_date).unwrap().with_timezone(&Local);
+    assert_eq!(item.date, expected.to_rfc3339_opts(SecondsFormat::Secs, false));
+
+    let invalid = get_snapshot_history(path_string(&dir), None, Some("weekday".to_string()), None, None, None).await.unwrap();
+    assert!(!invalid.success);
+    assert!(invalid.error.unwrap().contains("weekday"));
+
+    *APP_CONFIG_PATH.lock().unwrap() = None;
+    *DATE_DISPLAY_SETTINGS.lock().unwrap() = None;
+}
+
+// ---------- 提交消息风格 ----------
+
+#[test]
+fn conventional_message_uses_category_type() {
+    assert_eq!(build_commit_message(" 添加登录页 ", Some("feature"), "conventional"), "feat: 添加登录页");
+    assert_eq!(build_commit_message("修复崩溃", Some(" BugFix "), "conventional"), "fix: 修复崩溃");
+    assert_eq!(build_commit_message("试验", Some("experiment"), "conventional"), "chore: 试验");
+    assert_eq!(build_commit_message("其他", None, "conventional"), "chore: 其他");
+    assert_eq!(build_commit_message("其他", Some("unknown"), "conventional"), "chore: 其他");
+}
+
+#[test]
+fn plain_and_vibe_message_styles() {
+    assert_eq!(build_commit_message("  原样 ", Some("feature"), "plain"), "原样");
+    assert_eq!(build_commit_message("提示词", Some("feature"), "vibe"), "[Vibe] AI Prompt: 提示词");
+    assert_eq!(build_commit_message("提示词", None, "未知风格"), "[Vibe] AI Prompt: 提示词");
+}
+
+#[tokio::test]
+async fn create_snapshot_writes_conventional_subject() {
+    let dir = init_repo("conventional-subject");
+    fs::write(dir.join("a.txt"), "1").unwrap();
+
+    let options = SnapshotOptions {
+        category: Some("bugfix".to_string()),
+        message_style: Some("conventional".to_string()),
+        ..SnapshotOptions::default()
+    };
+
+    let result = create_snapshot_with_options(system_git(), path_string(&dir), "修复登录".to_string(), options, None).await.unwrap();
+
+    assert!(result.success, "{:?}", result.error);
+    assert_eq!(git(&dir, &["log", "-1", "--format=%s"]).trim(), "fix: 修复登录");
+}
+
+#[tokio::test]
+async fn auto_commit_uses_configured_message_style() {
+    let dir = repo_with_commits("auto-commit-style", 1);
+    let log_file = dir.join("prompts.log");
+    fs::write(&log_file, "整理样式\n").unwrap();
+    fs::write(dir.join("file.txt"), "changed").unwrap();
+    let mut config = watcher_config(&dir);
+    config.log_file_path = Some(path_string(&log_file));
+    config.message_style = "conventional".to_string();
+
+    let result = auto_commit_changes(&SystemGitRunner, &config).await.unwrap();
+
+    assert!(result.success, "{:?}", result.error);
+    assert_eq!(git(&dir, &["log", "-1", "--format=%s"]).trim(), "chore: 整理样式");
+}
+
+// ---------- 快照统计摘要 ----------
+
+#[tokio::test]
+async fn commit_stat_counts_lines_and_binary_files() {
+    let dir = init_repo("commit-stat");
+    fs::write(dir.join("a.txt"), "1\n2\n3\n").unwrap();
+    git(&dir, &["add", "."]);
+    git(&dir, &["commit", "-qm", "root"]);
+
+    // 根提交与空树比较
+    let root = get_commit_stat(path_string(&dir), "HEAD".to_string()).await.unwrap();
+    assert!(root.success, "{:?}", root.error);
+    assert_eq!((root.files, root.insertions, root.deletions, root.binary_files), (1, 3, 0, 0));
+
+    fs::write(dir.join("a.txt"), "1\nzwei\n").unwrap();
+    fs::write(dir.join("image.bin"), [0u8, 159, 146, 150, 0, 1]).unwrap();
+    git(&dir, &["add", "."]);
+    git(&dir, &["commit", "-qm", "second"]);
+
+    let stat = get_commit_stat(path_string(&dir), "HEAD".to_string()).await.unwrap();
+    assert!(stat.success, "{:?}", stat.error);
+    assert_eq!((stat.files, stat.insertions, stat.deletions, stat.binary_files), (2, 1, 2, 1));
+}
+
+#[tokio::test]
+async fn commit_stat_reports_errors() {
+    let dir = repo_with_commits("commit-stat-errors", 1);
+    let empty = get_commit_stat(path_string(&dir), "  ".to_string()).await.unwrap();
+    assert!(!empty.success);
+    let unknown = get_commit_stat(path_string(&dir), "0123456789abcdef".to_string()).await.unwrap();
+    assert!(!unknown.success);
+    assert!(unknown.error.unwrap().starts_with("Git show 失败"));
+}
+
+#[test]
+fn sum_numstat_skips_malformed_lines() {
+    let stat = sum_numstat("3\t1\tsrc/a.rs\n-\t-\tlogo.png\n无效的行\n\n10\t0\tdir/with\ttab.txt\n");
+    assert_eq!((stat.files, stat.insertions, stat.deletions, stat.binary_files), (3, 13, 1, 1));
+}
+
+// ---------- 合并自动快照 ----------
+
+// 一个手动快照之后跟着 count 个自动快照
+async fn repo_with_auto_snapshots(name: &str, count: usize) -> PathBuf {
+    let dir = init_repo(name);
+    fs::write(dir.join("a.txt"), "manual").unwrap();
+    let manual = create_snapshot_with_options(system_git(), path_string(&dir), "手动".to_string(), SnapshotOptions::default(), None).await.unwrap();
+    assert!(manual.success, "{:?}", manual.error);
+    for i in 0..count {
+        fs::write(dir.join("a.txt"), format!("auto {}", i)).unwrap();
+        let auto = auto_commit_changes(&SystemGitRunner, &watcher_config(&dir)).await.unwrap();
+        assert!(auto.success, "{:?}", auto.error);
+    }
+    dir
+}
+
+// 合并前面的自动快照并创建手动快照
+async fn fold_snapshot(dir: &Path) -> SnapshotResult {
+    let options = SnapshotOptions {
+        fold_auto_commits: Some(true),
+        ..SnapshotOptions::default()
+    };
+    create_snapshot_with_options(system_git(), path_string(dir), "合并".to_string(), options, None).await.unwrap()
+}
+
+#[tokio::test]
+async fn fold_replaces_auto_snapshots_with_manual_snapshot() {
+    let dir = repo_with_auto_snapshots("fold-success", 3).await;
+    fs::write(dir.join("b.txt"), "b").unwrap();
+
+    let result = fold_snapshot(&dir).await;
+
+    assert!(result.success, "{:?}", result.error);
+    assert_eq!(result.folded_commits, 3);
+    assert_eq!(git(&dir, &["rev-list", "--count", "HEAD"]).trim(), "2");
+    assert!(git(&dir, &["branch", "--list", "vibesnap/backup-*"]).contains("vibesnap/backup-"));
+}
+
+#[tokio::test]
+async fn fold_stops_at_tagged_auto_snapshot() {
+    let dir = repo_with_auto_snapshots("fold-tagged", 3).await;
+    git(&dir, &["tag", "-a", "keep", "-m", "保留", "HEAD~1"]);
+    fs::write(dir.join("b.txt"), "b").unwrap();
+
+    let result = fold_snapshot(&dir).await;
+
+    assert!(result.success, "{:?}", result.error);
+    assert_eq!(result.folded_commits, 1);
+    assert!(tagged_commits(&SystemGitRunner, &dir).unwrap().contains(git(&dir, &["rev-parse", "keep^{commit}"]).trim()));
+}
+
+#[tokio::test]
+async fn fold_is_undone_when_nothing_is_committed() {
+    let dir = repo_with_auto_snapshots("fold-nothing", 1).await;
+    // 第二个自动快照恢复了手动快照的内容，合并后相对边界没有任何修改
+    fs::write(dir.join("a.txt"), "manual").unwrap();
+    assert!(auto_commit_changes(&SystemGitRunner, &watcher_config(&dir)).await.unwrap().success);
+    let head = git(&dir, &["rev-parse", "HEAD"]);
+
+    let result = fold_snapshot(&dir).await;
+
+    assert!(!result.success);
+    assert!(result.no_changes);
+    assert_eq!(git(&dir, &["rev-parse", "HEAD"]), head);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn fold_is_undone_when_a_hook_rejects_the_commit() {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = repo_with_auto_snapshots("fold-hook", 2).await;
+    let head = git(&dir, &["rev-parse", "HEAD"]);
+    let hook = dir.join(".git/hooks/pre-commit");
+    fs::create_dir_all(hook.parent().unwrap()).unwrap();
+    fs::write(&hook, "#!/bin/sh\necho rejected >&2\nexit 1\n").unwrap();
+    fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(dir.join("b.txt"), "b").unwrap();
+
+    let result = fold_snapshot(&dir).await;
+
+    assert!(!result.success);
+    assert_eq!(git(&dir, &["rev-parse", "HEAD"]), head);
+    // 新的修改仍在暂存区，修复钩子后可以重新提交
+    assert_eq!(git(&dir, &["diff", "--cached", "--name-only"]).trim(), "b.txt");
+}
+
+#[tokio::test]
+async fn fold_is_undone_when_secrets_are_rejected() {
+    let dir = repo_with_auto_snapshots("fold-secret", 2).await;
+    let head = git(&dir, &["rev-parse", "HEAD"]);
+    fs::write(dir.join("config.env"), "password = hunter2\n").unwrap();
+    let options = SnapshotOptions {
+        fold_auto_commits: Some(true),
+        enforce_no_secrets: Some(true),
+        ..SnapshotOptions::default()
+    };
+    let result = create_snapshot_with_options(system_git(), path_string(&dir), "合并".to_string(), options, None).await.unwrap();
+    assert!(!result.success);
+    assert!(result.secret_scan_result.is_some());
+    assert_eq!(git(&dir, &["rev-parse", "HEAD"]), head);
+}
+
+// ---------- 监听暂存区（索引模式） ----------
+
+#[tokio::test]
+async fn index_mode_commits_only_staged_content() {
+    let dir = repo_with_commits("index-mode", 1);
+    fs::write(dir.join("file.txt"), "staged").unwrap();
+    fs::write(dir.join("other.txt"), "unstaged").unwrap();
+    git(&dir, &["add", "file.txt"]);
+    let mut config = watcher_config(&dir);
+    config.watch_git_index = true;
+
+    let result = auto_commit_changes(&SystemGitRunner, &config).await.unwrap();
+
+    assert!(result.success, "{:?}", result.error);
+    assert_eq!(git(&dir, &["show", "--name-only", "--format=", "HEAD"]).trim(), "file.txt");
+    assert_eq!(git(&dir, &["status", "--porcelain"]).trim(), "?? other.txt");
+
+    // 暂存区已清空，再次触发时不提交
+    let again = auto_commit_changes(&SystemGitRunner, &config).await.unwrap();
+    assert!(!again.success && again.no_changes);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn index_mode_failing_hook_does_not_commit_unstaged_content() {
+    let dir = repo_with_commits("index-mode-hook", 1);
+    fs::write(dir.join("other.txt"), "tracked").unwrap();
+    git(&dir, &["add", "other.txt"]);
+    git(&dir, &["commit", "-qm", "other"]);
+    let head = git(&dir, &["rev-parse", "HEAD"]);
+    install_hook(&dir, "pre-commit", FAIL_ONCE_HOOK);
+
+    fs::write(dir.join("file.txt"), "staged").unwrap();
+    git(&dir, &["add", "file.txt"]);
+    fs::write(dir.join("file.txt"), "staged then edited").unwrap();
+    fs::write(dir.join("other.txt"), "unstaged edit").unwrap();
+    let mut config = watcher_config(&dir);
+    config.watch_git_index = true;
+
+    let result = auto_commit_changes(&SystemGitRunner, &config).await.unwrap();
+
+    // 钩子没有改写文件，不应把工作区中未暂存的修改重新暂存后重试
+    assert!(!result.success);
+    assert_eq!(git(&dir, &["rev-parse", "HEAD"]), head);
+    assert_eq!(git(&dir, &["show", ":file.txt"]), "staged");
+    assert_eq!(git(&dir, &["status", "--porcelain"]).trim(), "MM file.txt\n M other.txt");
+}
+
+#[test]
+fn git_index_path_detection() {
+    assert!(is_git_index_path(Path::new("/repo/.git/index")));
+    assert!(is_git_index_path(Path::new(".git/index")));
+    assert!(!is_git_index_path(Path::new("/repo/.git/index.lock")));
+    assert!(!is_git_index_path(Path::new("/repo/src/index")));
+    assert!(!is_git_index_path(Path::new("/repo/.git/refs/index")));
+}
+
+// ---------- 单文件大小限制 ----------
+
+// 以给定的单文件大小限制和忽略方式创建快照
+async fn snapshot_with_size_limit(dir: &Path, max_mb: f64, ignore_mode: &str) -> SnapshotResult {
+    let options = SnapshotOptions {
+        max_file_size_mb: Some(max_mb),
+        large_file_ignore: Some(ignore_mode.to_string()),
+        ..SnapshotOptions::default()
+    };
+    create_snapshot_with_options(system_git(), path_string(dir), "提交".to_string(), options, None).await.unwrap()
+}
+
+#[tokio::test]
+async fn large_files_are_left_out_of_the_snapshot() {
+    let dir = init_repo("large-files");
+    fs::write(dir.join("a.txt"), "small").unwrap();
+    fs::write(dir.join("big.bin"), vec![b'x'; 2 * 1024 * 1024]).unwrap();
+
+    let result = snapshot_with_size_limit(&dir, 1.0, "exclude").await;
+
+    assert!(result.success, "{:?}", result.error);
+    assert_eq!(result.skipped_large_files, vec!["big.bin".to_string()]);
+    assert_eq!(git(&dir, &["ls-files"]), "a.txt\n");
+    assert!(fs::read_to_string(dir.join(".git/info/exclude")).unwrap().contains("/big.bin"));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn failed_auto_commit_still_reports_skipped_large_files() {
+    let dir = repo_with_commits("large-files-commit-fails", 1);
+    install_hook(&dir, "pre-commit", "#!/bin/sh\nexit 1\n");
+    fs::write(dir.join("file.txt"), "changed").unwrap();
+    fs::write(dir.join("big.bin"), vec![b'x'; 2 * 1024 * 1024]).unwrap();
+    let mut config = watcher_config(&dir);
+    config.max_file_size_mb = Some(1.0);
+
+    let result = auto_commit_changes(&SystemGitRunner, &config).await.unwrap();
+
+    assert!(!result.success);
+    assert_eq!(result.skipped_large_files, vec!["big.bin".to_string()]);
+}
+
+#[tokio::test]
+async fn only_large_files_changed_reports_them_without_committing() {
+    let dir = repo_with_commits("large-files-only", 1);
+    fs::write(dir.join("big.bin"), vec![b'x'; 2 * 1024 * 1024]).unwrap();
+
+    let result = snapshot_with_size_limit(&dir, 1.0, "none").await;
+
+    assert!(!result.success && result.no_changes);
+    assert_eq!(result.skipped_large_files, vec!["big.bin".to_string()]);
+    assert_eq!(git(&dir, &["rev-list", "--count", "HEAD"]).trim(), "1");
+}
+
+#[tokio::test]
+async fn non_positive_size_limit_is_rejected() {
+    let dir = repo_with_commits("large-files-invalid", 1);
+    fs::write(dir.join("file.txt"), "changed").unwrap();
+
+    for max_mb in [0.0, -1.0, f64::NAN] {
+        let result = snapshot_with_size_limit(&dir, max_mb, "none").await;
+        assert!(!result.success);
+        assert_eq!(result.message, "文件大小限制无效");
+    }
+    // 没有暂存或提交任何内容
+    assert_eq!(git(&dir, &["rev-list", "--count", "HEAD"]).trim(), "1");
+    assert!(git(&dir, &["diff", "--cached", "--name-only"]).is_empty());
+    assert!(validate_max_file_size(None).is_ok());
+    assert!(validate_max_file_size(Some(0.5)).is_ok());
+}
+
+#[tokio::test]
+async fn unknown_large_file_ignore_is_rejected() {
+    let dir = repo_with_commits("large-files-bad-ignore", 1);
+    fs::write(dir.join("big.bin"), vec![b'x'; 2 * 1024 * 1024]).unwrap();
+
+    let result = snapshot_with_size_limit(&dir, 1.0, "gitignroe").await;
+
+    assert!(!result.success);
+    assert_eq!(result.message, "超限文件的忽略方式无效");
+    assert!(result.error.unwrap().contains("gitignroe"));
+    assert!(git(&dir, &["diff", "--cached", "--name-only"]).is_empty());
+    assert!(validate_large_file_ignore("exclude").is_ok());
+}
+
+#[tokio::test]
+async fn saved_size_limit_applies_when_none_is_passed() {
+    let _guard = APP_CONFIG_TEST_LOCK.lock().await;
+    let dir = init_repo("large-files-saved");
+    let config_file = dir.join("config").join(APP_CONFIG_FILE);
+    init_app_config(config_file.clone());
+    let project = path_string(&dir);
+
+    assert!(set_max_file_size(project.clone(), Some(0.0)).await.is_err());
+    set_max_file_size(project.clone(), Some(1.0)).await.unwrap();
+    assert_eq!(get_max_file_size(project.clone()).await.unwrap(), Some(1.0));
+
+    fs::write(dir.join("a.txt"), "small").unwrap();
+    fs::write(dir.join("big.bin"), vec![b'x'; 2 * 1024 * 1024]).unwrap();
+    let result = create_snapshot_with_options(system_git(), project.clone(), "提交".to_string(), SnapshotOptions::default(), None)
+        .await
+        .unwrap();
+    assert!(result.success, "{:?}", result.error);
+    assert_eq!(result.skipped_large_files, vec!["big.bin".to_string()]);
+
+    // 清除后不再保留该项目的设置
+    set_max_file_size(project.clone(), None).await.unwrap();
+    assert_eq!(get_max_file_size(project).await.unwrap(), None);
+    assert!(load_app_config().unwrap().project_settings.is_empty());
+    *APP_CONFIG_PATH.lock().unwrap() = None;
+}
+
+// ---------- 只列出 VibeSnap 快照 ----------
+
+#[tokio::test]
+async fn vibe_snapshots_exclude_manual_commits() {
+    let dir = repo_with_commits("vibe-snapshots", 2);
+    git(&dir, &["commit", "--allow-empty", "-qm", "not [Vibe] at the start"]);
+    for prompt in ["第一次", "第二次", "第三次"] {
+        fs::write(dir.join("file.txt"), prompt).unwrap();
+        let result = create_snapshot_with_options(system_git(), path_string(&dir), prompt.to_string(), SnapshotOptions::default(), None).await.unwrap();
+        assert!(result.success, "{:?}", result.error);
+    }
+
+    let all = get_vibe_snapshots(path_string(&dir), None).await.unwrap();
+    assert!(all.success, "{:?}", all.error);
+    assert_eq!(all.total_count, 3);
+    let prompts: Vec<_> = all.history.iter().map(|item| item.prompt.clone().unwrap()).collect();
+    assert_eq!(prompts, vec!["第三次", "第二次", "第一次"]);
+
+    let limited = get_vibe_snapshots(path_string(&dir), Some(1)).await.unwrap();
+    assert_eq!(limited.history.len(), 1);
+    assert_eq!(limited.total_count, 3);
+}
+
+#[tokio::test]
+async fn vibe_snapshots_limit_counts_only_vibe_subjects() {
+    let dir = repo_with_commits("vibe-snapshots-body", 1);
+    fs::write(dir.join("file.txt"), "vibe").unwrap();
+    let result = create_snapshot_with_options(system_git(), path_string(&dir), "快照".to_string(), SnapshotOptions::default(), None)
+        .await
+        .unwrap();
+    assert!(result.success, "{:?}", result.error);
+    // 正文中以前缀开头的行也会被 --grep 匹配，这类手动提交不能占用 limit
+    let body = format!("{}引用的提示词", VIBE_COMMIT_PREFIX);
+    git(&dir, &["commit", "--allow-empty", "-qm", "手动提交", "-m", &body]);
+    git(&dir, &["commit", "--allow-empty", "-qm", "[Vibe] 前缀不完整"]);
+
+    let limited = get_vibe_snapshots(path_string(&dir), Some(1)).await.unwrap();
+    assert!(limited.success, "{:?}", limited.error);
+    assert_eq!(limited.total_count, 1);
+    let prompts: Vec<_> = limited.history.iter().map(|item| item.prompt.clone().unwrap()).collect();
+    assert_eq!(prompts, vec!["快照"]);
+}
+
+#[tokio::test]
+async fn vibe_snapshots_require_a_repository() {
+    let dir = temp_project("vibe-snapshots-not-repo");
+    let result = get_vibe_snapshots(path_string(&dir), None).await.unwrap();
+    assert!(!result.success);
+    assert_eq!(result.error.as_deref(), Some("项目不是 Git 仓库"));
+}
+
+// ---------- 提交钩子 ----------
+
+#[cfg(unix)]
+fn install_hook(dir: &Path, name: &str, script: &str) {
+    use std::os::unix::fs::PermissionsExt;
+    let hook = dir.join(".git/hooks").join(name);
+    fs::create_dir_all(hook.parent().unwrap()).unwrap();
+    fs::write(&hook, script).unwrap();
+    fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+// 像格式化工具一样去掉行尾空格，修改了文件时让本次提交失败
+#[cfg(unix)]
+const TRIM_HOOK: &str = "#!/bin/sh\nif grep -q ' $' file.txt; then sed -i.bak 's/ *$//' file.txt; rm -f file.txt.bak; exit 1; fi\nexit 0\n";
+
+// 不修改任何文件，只在第一次运行时失败；若被误判为钩子改写了文件而重试，第二次提交就会成功
+#[cfg(unix)]
+const FAIL_ONCE_HOOK: &str = "#!/bin/sh\nif [ -f .git/hook-ran ]; then exit 0; fi\ntouch .git/hook-ran\nexit 1\n";
+
+#[cfg(unix)]
+#[tokio::test]
+async fn bypass_hooks_skips_failing_hook() {
+    let dir = repo_with_commits("hooks-bypass", 1);
+    install_hook(&dir, "pre-commit", "#!/bin/sh\nexit 1\n");
+    fs::write(dir.join("file.txt"), "changed").unwrap();
+
+    let blocked = create_snapshot_with_options(system_git(), path_string(&dir), "提交".to_string(), SnapshotOptions::default(), None)
+        .await
+        .unwrap();
+    assert!(!blocked.success);
+
+    let options = SnapshotOptions {
+        bypass_hooks: Some(true),
+        ..SnapshotOptions::default()
+    };
+    let bypassed = create_snapshot_with_options(system_git(), path_string(&dir), "提交".to_string(), options, None).await.unwrap();
+    assert!(bypassed.success, "{:?}", bypassed.error);
+    assert_eq!(bypassed.hook_status.as_deref(), Some("bypassed"));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn hook_modified_files_are_restaged_and_retried() {
+    let dir = repo_with_commits("hooks-retry", 1);
+    install_hook(&dir, "pre-commit", TRIM_HOOK);
+    fs::write(dir.join("file.txt"), "dirty   \n").unwrap();
+
+    let result = auto_commit_changes(&SystemGitRunner, &watcher_config(&dir)).await.unwrap();
+
+    assert!(result.success, "{:?}", result.error);
+    assert_eq!(result.hook_status.as_deref(), Some("retried"));
+    assert_eq!(git(&dir, &["show", "HEAD:file.txt"]), "dirty\n");
+    assert!(git(&dir, &["status", "--porcelain"]).trim().is_empty());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn hook_retry_only_restages_previously_staged_files() {
+    let dir = repo_with_commits("hooks-retry-index", 1);
+    fs::write(dir.join("other.txt"), "tracked").unwrap();
+    git(&dir, &["add", "other.txt"]);
+    git(&dir, &["commit", "-qm", "other"]);
+    install_hook(&dir, "pre-commit", TRIM_HOOK);
+
+    // 索引模式：AI 工具只暂存了 file.txt，other.txt 的修改不属于本次提交
+    fs::write(dir.join("file.txt"), "dirty   \n").unwrap();
+    git(&dir, &["add", "file.txt"]);
+    fs::write(dir.join("other.txt"), "unstaged edit").unwrap();
+    let mut config = watcher_config(&dir);
+    config.watch_git_index = true;
+
+    let result = auto_commit_changes(&SystemGitRunner, &config).await.unwrap();
+
+    assert!(result.success, "{:?}", result.error);
+    assert_eq!(result.hook_status.as_deref(), Some("retried"));
+    assert_eq!(git(&dir, &["show", "--name-only", "--format=", "HEAD"]).trim(), "file.txt");
+    assert_eq!(git(&dir, &["status", "--porcelain"]).trim(), "M other.txt");
+}
+
+// ---------- 保存触发文件 ----------
+
+#[test]
+fn save_trigger_path_ignores_blank_setting() {
+    let dir = temp_project("save-trigger-blank");
+    let mut config = watcher_config(&dir);
+    assert!(resolve_trigger_path(&config).is_none());
+    config.save_trigger_file = Some("  ".to_string());
+    assert!(resolve_trigger_path(&config).is_none());
+}
+
+#[cfg(unix)]
+#[test]
+fn save_trigger_matches_through_symlinked_project_path() {
+    // 模拟 macOS：项目路径是 /tmp/...，事件报告的是 /private/tmp/...
+    let root = temp_project("save-trigger-symlink");
+    let real = root.join("real");
+    fs::create_dir_all(&real).unwrap();
+    let link = root.join("link");
+    std::os::unix::fs::symlink(&real, &link).unwrap();
+    let mut config = watcher_config(&link);
+    config.save_trigger_file = Some(" .vibesnap-save ".to_string());
+
+    // 触发文件还不存在时也能解析
+    let trigger = resolve_trigger_path(&config).unwrap();
+    assert!(is_save_trigger(&real.join(".vibesnap-save"), &trigger));
+    assert!(is_save_trigger(&link.join(".vibesnap-save"), &trigger));
+
+    fs::write(real.join(".vibesnap-save"), "").unwrap();
+    assert!(is_save_trigger(&real.join(".vibesnap-save"), &trigger));
+    assert!(is_save_trigger(&link.join(".vibesnap-save"), &resolve_trigger_path(&config).unwrap()));
+    assert!(!is_save_trigger(&real.join("other.txt"), &trigger));
+}
+
+// ---------- 自动快照的提交作者 ----------
+
+#[tokio::test]
+async fn auto_snapshots_use_bot_identity() {
+    let dir = repo_with_commits("bot-identity", 1);
+    let mut config = watcher_config(&dir);
+    config.bot_identity = Some(CommitIdentity {
+        name: "VibeSnap Bot".to_string(),
+        email: "bot@vibesnap.local".to_string(),
+    });
+    fs::write(dir.join("file.txt"), "auto").unwrap();
+
+    let auto = auto_commit_changes(&SystemGitRunner, &config).await.unwrap();
+    assert!(auto.success, "{:?}", auto.error);
+    assert_eq!(git(&dir, &["log", "-1", "--format=%an <%ae>|%cn"]).trim(), "VibeSnap Bot <bot@vibesnap.local>|tester");
+
+    fs::write(dir.join("file.txt"), "manual").unwrap();
+    let manual = create_snapshot_with_options(system_git(), path_string(&dir), "手动".to_string(), SnapshotOptions::default(), None)
+        .await
+        .unwrap();
+    assert!(manual.success, "{:?}", manual.error);
+    assert_eq!(git(&dir, &["log", "-1", "--format=%an <%ae>"]).trim(), "tester <tester@example.com>");
+}
+
+#[tokio::test]
+async fn auto_snapshots_default_to_repository_identity() {
+    let dir = repo_with_commits("bot-identity-default", 1);
+    fs::write(dir.join("file.txt"), "auto").unwrap();
+
+    let auto = auto_commit_changes(&SystemGitRunner, &watcher_config(&dir)).await.unwrap();
+
+    assert!(auto.success, "{:?}", auto.error);
+    assert_eq!(git(&dir, &["log", "-1", "--format=%an"]).trim(), "tester");
+    assert!(git(&dir, &["log", "-1", "--format=%B"]).contains(AUTO_SNAPSHOT_TRAILER));
+}
+
+// ---------- 最近一次手动提交 ----------
+
+#[tokio::test]
+async fn last_manual_snapshot_skips_vibe_snapshots() {
+    let dir = init_repo("last-manual");
+    assert!(get_last_manual_snapshot(path_string(&dir)).await.unwrap().is_none());
+
+    fs::write(dir.join("a.txt"), "1").unwrap();
+    git(&dir, &["add", "."]);
+    git(&dir, &["commit", "-qm", "手动提交"]);
+    for prompt in ["快照一", "快照二"] {
+        fs::write(dir.join("a.txt"), prompt).unwrap();
+        assert!(create_snapshot_with_options(system_git(), path_string(&dir), prompt.to_string(), SnapshotOptions::default(), None)
+            .await
+            .unwrap()
+            .success);
+    }
+    fs::write(dir.join("a.txt"), "auto").unwrap();
+    assert!(auto_commit_changes(&SystemGitRunner, &watcher_config(&dir)).await.unwrap().success);
+
+    let last = get_last_manual_snapshot(path_string(&dir)).await.unwrap().unwrap();
+    assert_eq!(last.message, "手动提交");
+    assert!(last.prompt.is_none());
+}
+
+#[tokio::test]
+async fn last_manual_snapshot_is_none_when_all_commits_are_snapshots() {
+    let dir = init_repo("last-manual-none");
+    fs::write(dir.join("a.txt"), "1").unwrap();
+    assert!(create_snapshot_with_options(system_git(), path_string(&dir), "快照".to_string(), SnapshotOptions::default(), None)
+        .await
+        .unwrap()
+        .success);
+
+    assert!(get_last_manual_snapshot(path_string(&dir)).await.unwrap().is_none());
+    assert!(get_last_manual_snapshot(path_string(&temp_project("last-manual-not-repo"))).await.is_err());
+}
+
+// ---------- 丢弃所有修改 ----------
+
+#[tokio::test]
+async fn discard_all_changes_restores_head() {
+    let dir = repo_with_commits("discard-all", 1);
+    fs::write(dir.join("file.txt"), "edited").unwrap();
+    fs::write(dir.join("new.txt"), "untracked").unwrap();
+    git(&dir, &["add", "file.txt"]);
+
+    let kept = discard_all_changes(path_string(&dir), None).await.unwrap();
+    assert!(kept.success, "{:?}", kept.error);
+    assert_eq!(fs::read_to_string(dir.join("file.txt")).unwrap(), "v1");
+    assert!(dir.join("new.txt").exists());
+
+    let removed = discard_all_changes(path_string(&dir), Some(true)).await.unwrap();
+    assert!(removed.success, "{:?}", removed.error);
+    assert!(!dir.join("new.txt").exists());
+    assert!(git(&dir, &["status", "--porcelain"]).is_empty());
+    assert!(!removed.is_detached_head);
+}
+
+#[tokio::test]
+async fn discard_all_changes_reports_detached_head() {
+    let dir = repo_with_commits("discard-detached", 2);
+    git(&dir, &["checkout", "--detach", "HEAD~1"]);
+    fs::write(dir.join("file.txt"), "edited").unwrap();
+
+    let result = discard_all_changes(path_string(&dir), None).await.unwrap();
+    assert!(result.success, "{:?}", result.error);
+    assert!(result.is_detached_head);
+}
+
+#[tokio::test]
+async fn quiet_window_only_applies_to_its_project() {
+    let first = repo_with_commits("quiet-window-a", 1);
+    let second = repo_with_commits("quiet-window-b", 1);
+    fs::write(first.join("file.txt"), "edited").unwrap();
+
+    assert!(discard_all_changes(path_string(&first), None).await.unwrap().success);
+
+    assert!(in_watcher_quiet_window(&path_string(&first)));
+    // 同一项目的另一种写法也处于静默期
+    assert!(in_watcher_quiet_window(&format!("{}/", path_string(&first))));
+    assert!(!in_watcher_quiet_window(&path_string(&second)));
+}
+
+// ---------- Windows 长路径 ----------
+
+#[test]
+fn event_paths_are_relative_to_the_watch_root() {
+    let root = Path::new("/home/user/app");
+    assert_eq!(relative_to_watch_root(&root.join("src/main.rs"), root), Path::new("src/main.rs"));
+    assert_eq!(relative_to_watch_root(Path::new("/elsewhere/main.rs"), root), Path::new("/elsewhere/main.rs"));
+}
+
+#[cfg(windows)]
+#[test]
+fn event_paths_match_either_form_of_a_verbatim_watch_root() {
+    let root = Path::new(r"\\?\C:\work\app");
+    assert_eq!(relative_to_watch_root(Path::new(r"\\?\C:\work\app\src\main.rs"), root), Path::new(r"src\main.rs"));
+    assert_eq!(relative_to_watch_root(Path::new(r"C:\work\app\src\main.rs"), root), Path::new(r"src\main.rs"));
+}
+
+#[test]
+fn long_path_config_is_only_set_on_windows() {
+    let dir = init_repo("long-path-config");
+    let longpaths = |command: &mut Command| {
+        let output = run_git(command.arg("config").arg("--get").arg("core.longpaths").current_dir(&dir)).unwrap();
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    };
+    let expected = if cfg!(windows) { "true" } else { "" };
+    assert_eq!(longpaths(&mut Command::new("git")), expected);
+
+    // 调用方自己设置了 GIT_CONFIG_COUNT 时不覆盖
+    assert_eq!(longpaths(Command::new("git").env("GIT_CONFIG_COUNT", "0")), "");
+}
+
+#[tokio::test]
+async fn snapshot_handles_deeply_nested_files() {
+    let dir = init_repo("long-paths");
+    let mut deep = dir.join("node_modules");
+    while deep.to_string_lossy().len() < 300 {
+        deep = deep.join("nested-package-directory");
+    }
+    fs::create_dir_all(&deep).unwrap();
+    fs::write(deep.join("index.js"), "module.exports = 1;\n").unwrap();
+
+    let result = create_snapshot_with_options(system_git(), path_string(&dir), "深层目录".to_string(), SnapshotOptions::default(), None)
+        .await
+        .unwrap();
+
+    assert!(result.success, "{:?}", result.error);
+    assert!(git(&dir, &["ls-files"]).trim().ends_with("nested-package-directory/index.js"));
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn snapshot_commits_and_skips_files_beyond_max_path() {
+    let dir = init_repo("long-paths-windows");
+    let mut deep = dir.join("node_modules");
+    while deep.to_string_lossy().len() < 260 {
+        deep = deep.join("nested-package-directory");
+    }
+    fs::create_dir_all(&deep).unwrap();
+    fs::write(deep.join("index.js"), "module.exports = 1;\n").unwrap();
+    fs::write(deep.join("bundle.bin"), vec![0u8; 2 * 1024 * 1024]).unwrap();
+    assert!(deep.join("index.js").to_string_lossy().len() > 260);
+
+    let result = snapshot_with_size_limit(&dir, 1.0, "exclude").await;
+
+    assert!(result.success, "{:?}", result.error);
+    assert_eq!(result.skipped_large_files.len(), 1);
+    assert!(result.skipped_large_files[0].ends_with("nested-package-directory/bundle.bin"));
+    assert!(fs::read_to_string(dir.join(".git/info/exclude")).unwrap().contains(&result.skipped_large_files[0]));
+    assert!(git(&dir, &["ls-files"]).trim().ends_with("nested-package-directory/index.js"));
+    assert!(git(&dir, &["status", "--porcelain", "--untracked-files=no"]).trim().is_empty());
+}
+
+// ---------- 提交签名配置 ----------
+
+#[tokio::test]
+async fn signing_config_accepts_git_boolean_spellings() {
+    let dir = init_repo("signing_bool");
+    for (value, enabled) in [("yes", true), ("on", true), ("1", true), ("TRUE", true), ("off", false), ("0", false)] {
+        git(&dir, &["config", "commit.gpgsign", value]);
+        let status = validate_git_signing_config(path_string(&dir)).await.unwrap();
+        assert_eq!(status.signing_enabled, enabled, "commit.gpgsign = {}", value);
+    }
+
+    // 没有等号的写法在 git 中表示 true
+    let config = dir.join(".git").join("config");
+    let content = fs::read_to_string(&config).unwrap().replace("gpgsign = 0", "gpgsign");
+    fs::write(&config, content).unwrap();
+    assert!(validate_git_signing_config(path_string(&dir)).await.unwrap().signing_enabled);
+}
+
+// ---------- 磁盘空间检查 ----------
+
+#[test]
+fn directory_size_skips_dependency_and_build_dirs() {
+    let dir = temp_project("size-estimate");
+    fs::write(dir.join("main.rs"), vec![b'a'; 100]).unwrap();
+    for heavy in ["node_modules/pkg", "target/debug", ".git/objects"] {
+        fs::create_dir_all(dir.join(heavy)).unwrap();
+        fs::write(dir.join(heavy).join("blob"), vec![b'b'; 10_000]).unwrap();
+    }
+    // 只有目录会被跳过，同名文件照常计算
+    fs::create_dir_all(dir.join("src")).unwrap();
+    fs::write(dir.join("src/target"), vec![b'c'; 20]).unwrap();
+
+    assert_eq!(estimate_directory_size(&dir), 120);
+}
+
+// ---------- 原始差异 ----------
+
+#[tokio::test]
+async fn raw_diff_is_only_returned_when_requested() {
+    let dir = repo_with_commits("raw-diff", 2);
+    let project = path_string(&dir);
+    let head = git(&dir, &["rev-parse", "HEAD"]).trim().to_string();
+
+    let without = get_friendly_diff_content(project.clone(), head.clone(), "file.txt".to_string(), None).await.unwrap();
+    assert!(without.success);
+    assert!(without.raw.is_none());
+
+    let with = get_friendly_diff_content(project, head, "file.txt".to_string(), Some(true)).await.unwrap();
+    let raw = with.raw.expect("请求时应返回原始差异");
+    assert!(raw.contains("-v1") && raw.contains("+v2"), "{}", raw);
+}
+
+#[tokio::test]
+async fn raw_diff_covers_root_and_unchanged_files() {
+    let dir = repo_with_commits("raw-diff-root", 1);
+    let project = path_string(&dir);
+    let root = git(&dir, &["rev-parse", "HEAD"]).trim().to_string();
+
+    let initial = get_friendly_diff_content(project.clone(), root, "file.txt".to_string(), Some(true)).await.unwrap();
+    let raw = initial.raw.expect("初始提交同样应返回原始差异");
+    assert!(raw.contains("new file mode") && raw.contains("+v1"), "{}", raw);
+
+    fs::write(dir.join("other.txt"), "other\n").unwrap();
+    git(&dir, &["add", "other.txt"]);
+    git(&dir, &["commit", "-qm", "other"]);
+    let head = git(&dir, &["rev-parse", "HEAD"]).trim().to_string();
+    let unchanged = get_friendly_diff_content(project, head, "file.txt".to_string(), Some(true)).await.unwrap();
+    assert_eq!(unchanged.summary.as_deref(), Some("此快照未对文件内容进行修改。"));
+    assert_eq!(unchanged.raw.as_deref(), Some(""));
+}
+
+// ---------- 修改速度 ----------
+
+fn commit_at(dir: &Path, message: &str, epoch_secs: i64) {
+    let date = format!("@{} +0000", epoch_secs);
+    Command::new("git")
+        .args(["commit", "-qam", message])
+        .env("GIT_AUTHOR_DATE", &date)
+        .env("GIT_COMMITTER_DATE", &date)
+        .current_dir(dir)
+        .output()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn change_velocity_has_no_peak_without_activity() {
+    let dir = init_repo("velocity_empty");
+    fs::write(dir.join("file.txt"), "old\n").unwrap();
+    git(&dir, &["add", "."]);
+    commit_at(&dir, "old", 1_000_000);
+
+    let stats = get_change_velocity(path_string(&dir), 24.0).await.unwrap();
+    assert_eq!(stats.commits_per_hour, 0.0);
+    assert_eq!(stats.peak_hour, None);
+    assert_eq!(stats.trough_hour, None);
+}
+
+#[tokio::test]
+async fn change_velocity_breaks_ties_toward_the_earliest_hour() {
+    let dir = init_repo("velocity_ties");
+    let now = Local::now().timestamp();
+    let mut hours = Vec::new();
+    for (i, offset) in [3 * 3600, 3600].into_iter().enumerate() {
+        fs::write(dir.join(format!("file{}.txt", i)), "line\n").unwrap();
+        git(&dir, &["add", "."]);
+        commit_at(&dir, &format!("commit {}", i), now - offset);
+        hours.push(DateTime::from_timestamp(now - offset, 0).unwrap().with_timezone(&Local).hour());
+    }
+
+    // 两个时段各新增 1 行，峰值取较早的时段；其余时段都没有修改，低谷取最早的空闲时段
+    let stats = get_change_velocity(path_string(&dir), 6.0).await.unwrap();
+    assert_eq!(stats.peak_hour, hours.iter().min().copied());
+    assert_eq!(stats.trough_hour, (0..24).find(|hour| !hours.contains(hour)));
+}
+
+// ---------- 提交模板 ----------
+
+#[tokio::test]
+async fn commit_template_sets_config_and_prefixes_snapshots() {
+    let dir = repo_with_commits("commit-template", 1);
+    let project = path_string(&dir);
+
+    let result = set_commit_template(project.clone(), Some("# 注释行\n[团队] 变更说明".to_string())).await.unwrap();
+    assert!(result.success, "{:?}", result.error);
+    let configured = git(&dir, &["config", "commit.template"]).trim().to_string();
+    assert_eq!(Some(configured.clone()), result.template_path);
+    assert_eq!(fs::read_to_string(&configured).unwrap(), "# 注释行\n[团队] 变更说明");
+
+    fs::write(dir.join("file.txt"), "changed").unwrap();
+    let options = SnapshotOptions { use_commit_template: Some(true), ..Default::default() };
+    let snapshot = create_snapshot_with_options(system_git(), project, "修改文件".to_string(), options, None)
+        .await
+        .unwrap();
+    assert!(snapshot.success, "{:?}", snapshot.error);
+    let message = git(&dir, &["log", "-1", "--format=%B"]);
+    assert!(message.starts_with("[团队] 变更说明\n\n"), "{}", message);
+    assert!(!message.contains("注释行"));
+}
+
+#[tokio::test]
+async fn clearing_unset_commit_template_is_a_no_op() {
+    let dir = repo_with_commits("commit-template-unset", 1);
+    let project = path_string(&dir);
+
+    let result = set_commit_template(project.clone(), None).await.unwrap();
+    assert!(result.success, "{:?}", result.error);
+    assert_eq!(read_commit_template(&SystemGitRunner, &dir), None);
+
+    // 未设置模板时开启 use_commit_template 不改变提交消息
+    fs::write(dir.join("file.txt"), "changed").unwrap();
+    let options = SnapshotOptions { use_commit_template: Some(true), ..Default::default() };
+    create_snapshot_with_options(system_git(), project, "修改文件".to_string(), options, None)
+        .await
+        .unwrap();
+    assert!(git(&dir, &["log", "-1", "--format=%B"]).starts_with("[Vibe] AI Prompt: 修改文件"));
+}
+
+// ---------- 文件路径规范化 ----------
+
+#[test]
+fn path_prefix_is_compared_per_component() {
+    let strip = |path: &str, prefix: &str, ignore_case| strip_path_prefix(Path::new(path), Path::new(prefix), ignore_case);
+    assert_eq!(strip("/Work/App/src/main.rs", "/work/app", true), Some(PathBuf::from("src/main.rs")));
+    assert_eq!(strip("/Work/App/src/main.rs", "/work/app", false), None);
+    // 只匹配完整的路径组件
+    assert_eq!(strip("/work/application/main.rs", "/work/app", true), None);
+    // 小写后字节长度变化的字符不会让切片偏移错位
+    assert_eq!(strip("/İ/app/main.rs", "/İ/app", true), Some(PathBuf::from("main.rs")));
+}
+
+#[test]
+fn absolute_and_backslash_paths_become_repo_relative() {
+    let dir = repo_with_commits("normalize-path", 1);
+    fs::create_dir_all(dir.join("src")).unwrap();
+    fs::write(dir.join("src/main.rs"), "fn main() {}\n").unwrap();
+
+    let absolute = path_string(&dir.join("src").join("main.rs"));
+    assert_eq!(normalize_repo_path(&SystemGitRunner, &dir, &absolute).unwrap(), "src/main.rs");
+    assert_eq!(normalize_repo_path(&SystemGitRunner, &dir, r"src\main.rs").unwrap(), "src/main.rs");
+    assert!(normalize_repo_path(&SystemGitRunner, &dir, "../outside.txt").is_err());
+    assert!(normalize_repo_path(&SystemGitRunner, &dir, &path_string(&std::env::temp_dir().join("outside.txt"))).is_err());
+}
+
+#[tokio::test]
+async fn directory_diff_accepts_absolute_paths() {
+    let dir = repo_with_commits("directory-diff-path", 1);
+    fs::create_dir_all(dir.join("src")).unwrap();
+    fs::write(dir.join("src/lib.rs"), "pub fn f() {}\n").unwrap();
+    git(&dir, &["add", "."]);
+    git(&dir, &["commit", "-qm", "src"]);
+    let head = git(&dir, &["rev-parse", "HEAD"]).trim().to_string();
+    let project = path_string(&dir);
+
+    let diff = get_directory_diff(project.clone(), head.clone(), path_string(&dir.join("src"))).await.unwrap();
+    assert!(diff.success, "{:?}", diff.error);
+    assert_eq!(diff.files, vec!["src/lib.rs".to_string()]);
+
+    let content = get_directory_diff_content(project, head, r"src\".to_string()).await.unwrap();
+    assert!(content.diff_content.unwrap().contains("+pub fn f() {}"));
+}
+
+// ---------- 用户空闲检测 ----------
+
+// 用户活动时间是全局状态，读写它的测试串行执行
+static USER_ACTIVITY_TEST_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+#[tokio::test]
+async fn reported_activity_resets_idle_timer() {
+    let _guard = USER_ACTIVITY_TEST_LOCK.lock().await;
+    report_activity().await.unwrap();
+    assert!(remaining_user_idle_wait(Duration::from_secs(60)).unwrap() > Duration::from_secs(59));
+    assert_eq!(remaining_user_idle_wait(Duration::ZERO), None);
+
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    let before_reset = remaining_user_idle_wait(Duration::from_millis(100)).unwrap();
+    assert!(before_reset <= Duration::from_millis(40));
+    assert_eq!(remaining_user_idle_wait(Duration::from_millis(50)), None);
+
+    // 新的活动重新开始计时
+    report_activity().await.unwrap();
+    assert!(remaining_user_idle_wait(Duration::from_millis(100)).unwrap() > Duration::from_millis(60));
+}
+
+// ---------- 迁移已有仓库 ----------
+
+#[tokio::test]
+async fn migration_tags_commits_in_one_batch() {
+    let dir = repo_with_commits("migrate-batch", 5);
+    fs::write(dir.join("file.txt"), "snapshot").unwrap();
+    git(&dir, &["commit", "-qam", "[Vibe] AI Prompt: 已经是快照"]);
+    let project = path_string(&dir);
+
+    let preview = migrate_from_manual_repo(project.clone(), false).await.unwrap();
+    assert_eq!((preview.tagged_count, preview.skipped_count), (5, 1));
+    let notes_ref = format!("refs/notes/{}", VIBE_NOTES_REF);
+    assert!(git(&dir, &["for-each-ref", &notes_ref]).is_empty());
+
+    let migrated = migrate_from_manual_repo(project.clone(), true).await.unwrap();
+    assert_eq!((migrated.tagged_count, migrated.skipped_count), (5, 1));
+    let notes = git(&dir, &["notes", &format!("--ref={}", VIBE_NOTES_REF), "list"]);
+    assert_eq!(notes.lines().count(), 5);
+    // 批量写入：notes 历史只有 add 和 copy 两个提交
+    assert_eq!(git(&dir, &["rev-list", "--count", &notes_ref]).trim(), "2");
+    let note = git(&dir, &["notes", &format!("--ref={}", VIBE_NOTES_REF), "show", "HEAD~1"]);
+    assert_eq!(note.trim(), VIBE_IMPORTED_NOTE);
+
+    let again = migrate_from_manual_repo(project, true).await.unwrap();
+    assert_eq!((again.tagged_count, again.skipped_count), (0, 6));
+}
+
+// ---------- 导出补丁 ----------
+
+#[tokio::test]
+async fn exported_patch_applies_to_a_clone() {
+    let dir = repo_with_commits("export-patch", 2);
+    let clone = temp_project("export-patch-clone");
+    fs::remove_dir_all(&clone).unwrap();
+    git(&dir, &["clone", "-q", &path_string(&dir), &path_string(&clone)]);
+    git(&clone, &["reset", "-q", "--hard", "HEAD~1"]);
+    git(&clone, &["config", "user.name", "tester"]);
+    git(&clone, &["config", "user.email", "tester@example.com"]);
+
+    let patch = dir.join("head.patch");
+    let size = export_patch(path_string(&dir), "HEAD".to_string(), path_string(&patch)).await.unwrap();
+    assert_eq!(size, fs::metadata(&patch).unwrap().len());
+
+    git(&clone, &["am", "-q", &path_string(&patch)]);
+    assert_eq!(fs::read_to_string(clone.join("file.txt")).unwrap(), "v2");
+    assert_eq!(git(&clone, &["log", "-1", "--format=%s"]).trim(), "commit 2");
+}
+
+#[tokio::test]
+async fn root_commit_exports_and_invalid_hash_fails() {
+    let dir = repo_with_commits("export-patch-root", 1);
+    let patch = dir.join("root.patch");
+    export_patch(path_string(&dir), "HEAD".to_string(), path_string(&patch)).await.unwrap();
+    let content = fs::read_to_string(&patch).unwrap();
+    assert!(content.contains("new file mode") && content.contains("+v1"), "{}", content);
+
+    let error = export_patch(path_string(&dir), "not-a-commit".to_string(), path_string(&patch)).await;
+    assert!(error.is_err());
+}
+
+// ---------- 查找引入文本的快照 ----------
+
+#[test]
+fn text_change_counts_lines_that_look_like_file_headers() {
+    let patch = [
+        "diff --git a/query.sql b/query.sql",
+        "--- a/query.sql",
+        "+++ b/query.sql",
+        "@@ -1,2 +1,2 @@",
+        "--- TODO: remove",
+        "+++counter; // TODO: remove",
+        " select 1;",
+    ];
+    let (added, removed, excerpt) = summarize_text_change(&patch, "TODO");
+    assert_eq!((added, removed), (1, 1));
+    assert!(excerpt.starts_with("@@ -1,2 +1,2 @@"), "{}", excerpt);
+}
+
+#[tokio::test]
+async fn removed_sql_comment_is_found() {
+    let dir = init_repo("text-search-sql");
+    fs::write(dir.join("query.sql"), "-- FIXME slow query\nselect 1;\n").unwrap();
+    git(&dir, &["add", "."]);
+    git(&dir, &["commit", "-qm", "add query"]);
+    fs::write(dir.join("query.sql"), "select 1;\n").unwrap();
+    git(&dir, &["commit", "-qam", "drop comment"]);
+
+    let result = find_snapshot_introducing_text(path_string(&dir), "FIXME".to_string(), None).await.unwrap();
+    assert!(result.found);
+    let types: Vec<&str> = result.changes.iter().map(|change| change.change_type.as_str()).collect();
+    assert_eq!(types, vec!["added", "removed"]);
+    assert_eq!(result.removed.unwrap().message, "drop comment");
+}
+
+// ---------- 应用补丁 ----------
+
+// 同时修改 a.txt 和 b.txt 的补丁，工作区中的 b.txt 已被改动，b.txt 的 hunk 无法应用
+fn repo_with_conflicting_patch(name: &str) -> PathBuf {
+    let dir = init_repo(name);
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    fs::write(dir.join("b.txt"), "b\n").unwrap();
+    git(&dir, &["add", "."]);
+    git(&dir, &["commit", "-qm", "base"]);
+    fs::write(dir.join("a.txt"), "a2\n").unwrap();
+    fs::write(dir.join("b.txt"), "b2\n").unwrap();
+    fs::write(dir.join("change.diff"), git(&dir, &["diff"])).unwrap();
+    git(&dir, &["checkout", "-q", "--", "."]);
+    fs::write(dir.join("b.txt"), "local edit\n").unwrap();
+    dir
+}
+
+#[tokio::test]
+async fn relative_patch_path_resolves_against_project() {
+    let dir = repo_with_conflicting_patch("patch-relative");
+    fs::write(dir.join("b.txt"), "b\n").unwrap();
+
+    let result = apply_patch(path_string(&dir), "change.diff".to_string(), false, None).await.unwrap();
+    assert!(result.success, "{:?}", result.error);
+    assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "a2\n");
+}
+
+#[tokio::test]
+async fn failed_patch_leaves_tree_untouched_by_default() {
+    let dir = repo_with_conflicting_patch("patch-atomic");
+
+    let result = apply_patch(path_string(&dir), "change.diff".to_string(), false, None).await.unwrap();
+    assert!(!result.success);
+    assert_eq!(result.conflicts, vec!["b.txt".to_string()]);
+    assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "a\n");
+    assert!(!dir.join("b.txt.rej").exists());
+}
+
+#[tokio::test]
+async fn partial_patch_rejects_are_not_snapshotted() {
+    let dir = repo_with_conflicting_patch("patch-partial");
+    let project = path_string(&dir);
+
+    let result = apply_patch(project.clone(), "change.diff".to_string(), false, Some(true)).await.unwrap();
+    assert_eq!(result.rejected_hunks, vec!["b.txt: hunk #1".to_string()]);
+    assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "a2\n");
+    assert!(dir.join("b.txt.rej").exists());
+
+    let snapshot = create_snapshot_with_options(system_git(), project, "部分应用补丁".to_string(), SnapshotOptions::default(), None)
+        .await
+        .unwrap();
+    assert!(snapshot.success, "{:?}", snapshot.error);
+    let committed = git(&dir, &["show", "--name-only", "--format=", "HEAD"]);
+    assert!(committed.contains("a.txt"));
+    assert!(!committed.contains(".rej"), "{}", committed);
+}
+
+// ---------- 友好差异中的 ++ / -- 行 ----------
+
+const DOUBLE_SIGN_DIFF: &str = "diff --git a/main.c b/main.c
+index 1111111..2222222 100644
+--- a/main.c
++++ b/main.c
+@@ -1,2 +1,2 @@
+ int i = 0;
+--- old comment
++++i;
+";
+
+#[test]
+fn friendly_diff_keeps_lines_starting_with_double_signs() {
+    let friendly = parse_friendly_diff(DOUBLE_SIGN_DIFF);
+    let lines: Vec<(&str, &str)> = friendly
+        .lines
+        .iter()
+        .map(|line| (line.change_type.as_str(), line.content.as_str()))
+        .collect();
+    assert_eq!(lines[1..], [("removed", "-- old comment"), ("added", "++i;")]);
+}
+
+#[test]
+fn filtered_diff_keeps_lines_starting_with_double_signs() {
+    let added = filter_diff_lines(DOUBLE_SIGN_DIFF, true);
+    assert_eq!(added.len(), 1);
+    assert_eq!((added[0].content.as_str(), added[0].new_line_number), ("++i;", Some(2)));
+
+    let removed = filter_diff_lines(DOUBLE_SIGN_DIFF, false);
+    assert_eq!(removed.len(), 1);
+    assert_eq!((removed[0].content.as_str(), removed[0].old_line_number), ("-- old comment", Some(2)));
+}
+
+#[tokio::test]
+async fn friendly_diff_content_shows_removed_sql_comment() {
+    let dir = init_repo("friendly-double-sign");
+    fs::write(dir.join("query.sql"), "-- comment\nselect 1;\n").unwrap();
+    git(&dir, &["add", "."]);
+    git(&dir, &["commit", "-qm", "add"]);
+    fs::write(dir.join("query.sql"), "select 1;\n").unwrap();
+    git(&dir, &["commit", "-qam", "remove comment"]);
+
+    let diff = get_friendly_diff_content(path_string(&dir), "HEAD".to_string(), "query.sql".to_string(), None)
+        .await
+        .unwrap();
+    assert!(diff.lines.iter().any(|line| line.change_type == "removed" && line.content == "-- comment"));
+    assert_eq!(diff.summary.as_deref(), Some("此快照从文件中删除了 1 行代码。"));
+}
+
+#[test]
+fn friendly_diff_context_lines_keep_leading_space() {
+    let friendly = parse_friendly_diff(DOUBLE_SIGN_DIFF);
+    assert_eq!((friendly.lines[0].change_type.as_str(), friendly.lines[0].content.as_str()), ("unchanged", " int i = 0;"));
+}
+
+#[tokio::test]
+async fn shared_diff_context_lines_have_single_marker_column() {
+    let dir = init_repo("shared-context");
+    fs::write(dir.join("a.txt"), "one\ntwo\n").unwrap();
+    git(&dir, &["add", "."]);
+    git(&dir, &["commit", "-qm", "add"]);
+    fs::write(dir.join("a.txt"), "one\nTWO\n").unwrap();
+    git(&dir, &["commit", "-qam", "edit"]);
+
+    let shared = format_diff_for_sharing(path_string(&dir), "HEAD".to_string(), Some("a.txt".to_string()), "markdown".to_string())
+        .await
+        .unwrap();
+    let content = shared.content.unwrap();
+    assert!(content.lines().any(|line| line == " one"), "{}", content);
+    assert!(content.lines().any(|line| line == "+TWO"), "{}", content);
+}
+
+// ---------- 提交图 ----------
+
+#[tokio::test]
+async fn graph_links_merge_parents() {
+    let dir = repo_with_commits("graph-merge", 1);
+    git(&dir, &["checkout", "-qb", "feature"]);
+    fs::write(dir.join("feature.txt"), "feature").unwrap();
+    git(&dir, &["add", "."]);
+    git(&dir, &["commit", "-qm", "[Vibe] AI Prompt: 新功能"]);
+    git(&dir, &["checkout", "-q", "master"]);
+    fs::write(dir.join("file.txt"), "main").unwrap();
+    git(&dir, &["commit", "-qam", "main work"]);
+    git(&dir, &["merge", "-q", "--no-ff", "-m", "merge feature", "feature"]);
+
+    let graph = get_graph(path_string(&dir), Some(10), None).await.unwrap();
+    assert!(graph.success, "{:?}", graph.error);
+    assert_eq!(graph.nodes.len(), 4);
+
+    let merge = &graph.nodes[0];
+    assert!(merge.is_merge);
+    let main_work = git(&dir, &["rev-parse", "--short", "HEAD^1"]).trim().to_string();
+    let feature = git(&dir, &["rev-parse", "--short", "HEAD^2"]).trim().to_string();
+    assert_eq!(merge.parents, vec![main_work, feature.clone()]);
+
+    let feature_node = graph.nodes.iter().find(|node| node.short_hash == feature).unwrap();
+    assert!(feature_node.is_vibe_snapshot);
+    assert!(!merge.is_vibe_snapshot);
+    let root = graph.nodes.last().unwrap();
+    assert!(root.parents.is_empty() && !root.is_merge);
+
+    let limited = get_graph(path_string(&dir), Some(2), None).await.unwrap();
+    assert_eq!(limited.nodes.len(), 2);
+}
+
+#[tokio::test]
+async fn linear_history_graph_is_flat() {
+    let dir = repo_with_commits("graph_linear", 3);
+    let graph = get_graph(path_string(&dir), None, Some(true)).await.unwrap();
+    assert!(graph.success, "{:?}", graph.error);
+    assert!(graph.nodes.iter().all(|node| node.lane_hint == 0));
+    assert_eq!((graph.lane_count, graph.edges.len(), graph.truncated), (1, 2, false));
+    assert!(graph.nodes[0].refs.contains(&"HEAD".to_string()));
+
+    let empty = init_repo("graph_empty");
+    let graph = get_graph(path_string(&empty), None, Some(true)).await.unwrap();
+    assert!(graph.success && graph.nodes.is_empty(), "{:?}", graph.error);
+}
+
+#[tokio::test]
+async fn all_branches_graph_skips_remote_and_internal_refs() {
+    let dir = repo_with_commits("graph_all_branches", 2);
+    git(&dir, &["checkout", "-qb", "experiment", "HEAD~1"]);
+    fs::write(dir.join("exp.txt"), "exp").unwrap();
+    git(&dir, &["add", "."]);
+    git(&dir, &["commit", "-qm", "try a | b"]);
+    git(&dir, &["tag", "v-exp"]);
+    git(&dir, &["checkout", "-q", "master"]);
+    // 远程跟踪分支和笔记引用指向的提交不属于本地分支图
+    fs::write(dir.join("remote.txt"), "remote").unwrap();
+    git(&dir, &["add", "."]);
+    git(&dir, &["commit", "-qm", "remote only"]);
+    git(&dir, &["update-ref", "refs/remotes/origin/master", "HEAD"]);
+    git(&dir, &["reset", "-q", "--hard", "HEAD~1"]);
+    git(&dir, &["notes", "--ref", "vibesnap", "add", "-m", "note", "HEAD"]);
+
+    let current = get_graph(path_string(&dir), None, None).await.unwrap();
+    assert_eq!(current.nodes.len(), 2);
+
+    let graph = get_graph(path_string(&dir), None, Some(true)).await.unwrap();
+    assert!(graph.success, "{:?}", graph.error);
+    let subjects: Vec<&str> = graph.nodes.iter().map(|node| node.message.as_str()).collect();
+    assert_eq!(subjects.len(), 3, "{:?}", subjects);
+    assert!(subjects.contains(&"try a | b") && !subjects.contains(&"remote only"));
+    let experiment = graph.nodes.iter().find(|node| node.message == "try a | b").unwrap();
+    assert!(experiment.refs.contains(&"tag: v-exp".to_string()), "{:?}", experiment.refs);
+    assert_eq!(graph.lane_count, 2);
+
+    let limited = get_graph(path_string(&dir), Some(2), Some(true)).await.unwrap();
+    assert!(limited.truncated && limited.nodes.len() == 2);
+}
+
+#[tokio::test]
+async fn linear_history_branch_graph_is_a_single_lane() {
+    let dir = repo_with_commits("branch_graph_linear", 4);
+    let graph = get_branch_graph(path_string(&dir), None).await.unwrap();
+    assert!(graph.success, "{:?}", graph.error);
+    assert_eq!(graph.nodes.len(), 4);
+    assert!(graph.nodes.iter().all(|node| node.lane_hint == 0 && node.kind == "commit"));
+    assert_eq!((graph.lane_count, graph.edges.len(), graph.truncated), (1, 3, false));
+    // 每条边都连接相邻的两个提交
+    for (edge, pair) in graph.edges.iter().zip(graph.nodes.windows(2)) {
+        assert_eq!((edge.child.as_str(), edge.parent.as_str()), (pair[0].full_hash.as_str(), pair[1].full_hash.as_str()));
+    }
+
+    let limited = get_branch_graph(path_string(&dir), Some(3)).await.unwrap();
+    assert!(limited.truncated && limited.nodes.len() == 3 && limited.lane_count == 1);
+}
+
+#[tokio::test]
+async fn branch_graph_includes_remote_branches_but_not_notes() {
+    let dir = repo_with_commits("branch_graph_all", 2);
+    fs::write(dir.join("remote.txt"), "remote").unwrap();
+    git(&dir, &["add", "."]);
+    git(&dir, &["commit", "-qm", "remote only"]);
+    git(&dir, &["update-ref", "refs/remotes/origin/master", "HEAD"]);
+    git(&dir, &["reset", "-q", "--hard", "HEAD~1"]);
+    git(&dir, &["notes", "--ref", "vibesnap", "add", "-m", "note", "HEAD"]);
+
+    let graph = get_branch_graph(path_string(&dir), None).await.unwrap();
+    assert!(graph.success, "{:?}", graph.error);
+    let subjects: Vec<&str> = graph.nodes.iter().map(|node| node.message.as_str()).collect();
+    assert_eq!(subjects, vec!["remote only", "commit 2", "commit 1"]);
+    assert!(graph.nodes[0].refs.contains(&"origin/master".to_string()), "{:?}", graph.nodes[0].refs);
+    assert_eq!(graph.lane_count, 1);
+
+    let empty = init_repo("branch_graph_empty");
+    let graph = get_branch_graph(path_string(&empty), None).await.unwrap();
+    assert!(graph.success && graph.nodes.is_empty(), "{:?}", graph.error);
+}
+
+// ---------- 工作区文件分类缓存 ----------
+
+fn pending_paths(files: &WorkingFilesByCategory) -> Vec<String> {
+    let mut paths: Vec<String> = [&files.new_files, &files.modified, &files.deleted]
+        .iter()
+        .flat_map(|entries| entries.iter().map(|entry| entry.path.clone()))
+        .collect();
+    paths.sort();
+    paths
+}
+
+#[tokio::test]
+async fn working_files_are_cached_until_a_watcher_event_or_index_change() {
+    let dir = repo_with_commits("status-cache", 1);
+    let project = path_string(&dir);
+    let (_window, _stop_rx, _stop_tx) = register_test_session(&project);
+
+    fs::write(dir.join("file.txt"), "changed").unwrap();
+    let first = get_working_files_by_category(project.clone()).await.unwrap();
+    assert_eq!(pending_paths(&first), vec!["file.txt"]);
+
+    // 没有监听器事件时直接使用缓存
+    fs::write(dir.join("new.txt"), "new").unwrap();
+    let cached = get_working_files_by_category(project.clone()).await.unwrap();
+    assert_eq!(pending_paths(&cached), vec!["file.txt"]);
+
+    bump_worktree_generation(&project);
+    let refreshed = get_working_files_by_category(project.clone()).await.unwrap();
+    assert_eq!(pending_paths(&refreshed), vec!["file.txt", "new.txt"]);
+
+    // 提交会改变 HEAD 和索引
+    git(&dir, &["add", "."]);
+    git(&dir, &["commit", "-qm", "commit all"]);
+    let committed = get_working_files_by_category(project.clone()).await.unwrap();
+    assert_eq!(committed.total_pending, 0);
+    stop_watcher_sessions(Some(&project));
+}
+
+#[tokio::test]
+async fn working_files_are_not_cached_without_a_watcher() {
+    let dir = repo_with_commits("status-no-cache", 1);
+    let project = path_string(&dir);
+
+    assert_eq!(get_working_files_by_category(project.clone()).await.unwrap().total_pending, 0);
+    fs::write(dir.join("file.txt"), "changed").unwrap();
+    let files = get_working_files_by_category(project).await.unwrap();
+    assert_eq!(pending_paths(&files), vec!["file.txt"]);
+    assert_eq!(files.modified[0].insertions, Some(1));
+}
+
+// ---------- 项目监听配置文件 ----------
+
+#[test]
+fn every_template_watcher_config_parses() {
+    for (name, _, _, watcher_config, _) in INIT_TEMPLATES {
+        let parsed: ProjectConfigFile = toml::from_str(watcher_config).unwrap_or_else(|e| panic!("{} 模板无法解析: {}", name, e));
+        assert!(parsed.watcher.debounce_ms.is_some(), "{} 模板缺少 debounce_ms", name);
+    }
+}
+
+#[test]
+fn project_watcher_defaults_come_from_vibesnap_toml() {
+    let dir = temp_project("watcher_defaults");
+    assert_eq!(read_project_watcher_defaults(&dir).unwrap(), ProjectWatcherDefaults::default());
+
+    fs::write(dir.join(".vibesnap.toml"), "[watcher]\ndebounce_ms = 10000\nmax_file_size_mb = 200\n").unwrap();
+    let defaults = read_project_watcher_defaults(&dir).unwrap();
+    assert_eq!(defaults.debounce_ms, Some(10000));
+    assert_eq!(defaults.max_file_size_mb, Some(200.0));
+    assert_eq!(defaults.max_wait_ms, None);
+
+    fs::write(dir.join(".vibesnap.toml"), "[watcher\ndebounce_ms = ").unwrap();
+    assert!(read_project_watcher_defaults(&dir).unwrap_err().contains(".vibesnap.toml"));
+}
+
+// ---------- 构建产物忽略规则 ----------
+
+#[test]
+fn build_artifact_ignores_come_from_init_templates() {
+    for (name, _, gitignore, _, _) in INIT_TEMPLATES {
+        for entry in build_artifact_ignores(name) {
+            assert!(gitignore.lines().any(|line| line.trim() == entry), "{} 不在 {} 模板中", entry, name);
+        }
+    }
+    assert!(build_artifact_ignores("node").contains(&"node_modules/".to_string()));
+    assert_eq!(build_artifact_ignores("rust"), vec!["target/".to_string()]);
+    assert_eq!(build_artifact_ignores("go"), vec!["bin/".to_string()]);
+    // 带通配符的规则不是具体目录
+    assert!(!build_artifact_ignores("python").iter().any(|entry| entry.contains('*')));
+    assert!(build_artifact_ignores("unknown").is_empty());
+}
+
+#[test]
+fn every_detected_project_type_has_a_template() {
+    let dir = temp_project("detect-java");
+    fs::write(dir.join("pom.xml"), "<project/>").unwrap();
+    assert_eq!(detect_project_type(&dir), Some("java"));
+    assert_eq!(detect_init_template(&dir), "java");
+}
+
+#[tokio::test]
+async fn convert_without_gitignore_generation_leaves_gitignore_untouched() {
+    let dir = temp_project("convert-no-gitignore");
+    fs::write(dir.join("package.json"), "{}").unwrap();
+    fs::create_dir_all(dir.join("node_modules")).unwrap();
+    fs::write(dir.join(".gitignore"), "secret.env").unwrap();
+
+    let (result, summary) = convert_project(path_string(&dir), "demo".to_string(), false).await.unwrap();
+    assert!(result.success, "{:?}", result.error);
+    assert!(summary.gitignore_entries.is_empty());
+    assert_eq!(fs::read_to_string(dir.join(".gitignore")).unwrap(), "secret.env");
+
+    // 没有 .gitignore 时也不会创建
+    let bare = temp_project("convert-no-gitignore-bare");
+    fs::write(bare.join("Cargo.toml"), "[package]").unwrap();
+    let (result, _) = convert_project(path_string(&bare), "bare".to_string(), false).await.unwrap();
+    assert!(result.success, "{:?}", result.error);
+    assert!(!bare.join(".gitignore").exists());
+}
+
+// ---------- 过长的防抖时间 ----------
+
+#[test]
+fn long_debounce_without_max_wait_warns() {
+    let warning = debounce_warning(60_000, None).expect("防抖时间过长时应提示");
+    assert!(warning.contains("60 秒") && warning.contains("max_wait"), "{}", warning);
+
+    assert_eq!(debounce_warning(60_000, Some(120_000)), None);
+    assert_eq!(debounce_warning(LONG_DEBOUNCE_WARNING_MS, None), None);
+    assert_eq!(debounce_warning(2_000, None), None);
+}
+
+// ---------- 合并同一路径的文件事件 ----------
+
+fn idle_window(now: Instant) -> DebounceWindow {
+    DebounceWindow {
+        active: false,
+        started_at: now,
+        deadline: now,
+        changed_paths: HashSet::new(),
+        active_override: None,
+        cancel_count: 0,
+    }
+}
+
+#[test]
+fn event_storm_starts_one_timer_per_quiet_period() {
+    let now = Instant::now();
+    let debounce = Duration::from_millis(100);
+    let mut window = idle_window(now);
+
+    let mut timers = 0;
+    let mut new_paths = 0;
+    // 每次保存产生临时文件、重命名和多次修改事件
+    for i in 0..60u64 {
+        let path = ["src/app.ts", "src/app.ts.tmp", "src/util.ts"][(i % 3) as usize].to_string();
+        let (start_timer, has_new_path) = record_debounce_event(&mut window, vec![path], now + Duration::from_millis(i), debounce, None, &[]);
+        timers += start_timer as usize;
+        new_paths += has_new_path as usize;
+    }
+    assert_eq!((timers, new_paths), (1, 3));
+    // 截止时间原地顺延到最后一个事件之后
+    assert_eq!(window.deadline, now + Duration::from_millis(59) + debounce);
+    assert_eq!(window.changed_paths.len(), 3);
+
+    // 计时器结束本轮后，下一次事件开始新的一轮
+    window.active = false;
+    let later = now + Duration::from_secs(1);
+    let (start_timer, has_new_path) = record_debounce_event(&mut window, vec!["src/app.ts".to_string()], later, debounce, None, &[]);
+    assert!(start_timer && has_new_path);
+    assert_eq!(window.changed_paths.len(), 1);
+}
+
+#[test]
+fn event_storm_respects_max_wait() {
+    let now = Instant::now();
+    let mut window = idle_window(now);
+    for i in 0..20u64 {
+        record_debounce_event(
+            &mut window,
+            vec!["a.txt".to_string()],
+            now + Duration::from_millis(i * 10),
+            Duration::from_millis(100),
+            Some(Duration::from_millis(150)),
+            &[],
+        );
+    }
+    assert_eq!(window.deadline, now + Duration::from_millis(150));
+}
+
+// ---------- 文件跟踪状态 ----------
+
+#[tokio::test]
+async fn tracking_state_distinguishes_untracked_and_ignored() {
+    let dir = repo_with_commits("tracking-state", 1);
+    fs::write(dir.join(".gitignore"), "*.log\n").unwrap();
+    fs::write(dir.join("debug.log"), "log").unwrap();
+    fs::write(dir.join("new.txt"), "new").unwrap();
+    let project = path_string(&dir);
+    let state = |path: &str| {
+        let project = project.clone();
+        let path = path.to_string();
+        async move { is_tracked(project, path).await.unwrap() }
+    };
+
+    let tracked = state("file.txt").await;
+    assert!(tracked.tracked);
+    assert_eq!(tracked.state, "tracked");
+    let untracked = state("new.txt").await;
+    assert!(!untracked.tracked);
+    assert_eq!(untracked.state, "untracked");
+    assert_eq!(state("debug.log").await.state, "ignored");
+    assert_eq!(state("missing.txt").await.state, "not_found");
+    // 绝对路径同样可以判断
+    assert_eq!(state(&path_string(&dir.join("file.txt"))).await.state, "tracked");
+}
+
+// ---------- 按路径的防抖覆盖规则 ----------
+
+fn debounce_overrides(rules: &[(&str, u64)]) -> Vec<(glob::Pattern, DebounceOverride)> {
+    rules
+        .iter()
+        .map(|(glob, debounce_ms)| {
+            (glob::Pattern::new(glob).unwrap(), DebounceOverride { glob: glob.to_string(), debounce_ms: *debounce_ms })
+        })
+        .collect()
+}
+
+#[test]
+fn overlapping_overrides_pick_the_longest() {
+    let overrides = debounce_overrides(&[("*.json", 3000), ("schema.json", 10000), ("api/**", 5000)]);
+    let matched = |path: &str| match_debounce_override(path, &overrides).map(|matched| matched.glob.as_str());
+    assert_eq!(matched("api/schema.json"), Some("schema.json"));
+    assert_eq!(matched("api/v1/users.json"), Some("api/**"));
+    assert_eq!(matched("config/app.json"), Some("*.json"));
+    assert_eq!(matched("README.md"), None);
+}
+
+#[test]
+fn override_globs_do_not_cross_separators() {
+    let overrides = debounce_overrides(&[("generated/*", 8000), ("docs/**", 6000)]);
+    let matched = |path: &str| match_debounce_override(path, &overrides).map(|matched| matched.glob.as_str());
+    assert_eq!(matched("generated/types.ts"), Some("generated/*"));
+    assert_eq!(matched("generated/nested/types.ts"), None);
+    assert_eq!(matched("docs/guide/intro.md"), Some("docs/**"));
+}
+
+#[test]
+fn effective_debounce_is_at_least_the_global_value() {
+    let overrides = debounce_overrides(&[("src/**", 500), ("*.json", 3000), ("schema.json", 10000)]);
+    let global = Duration::from_millis(2000);
+    let now = Instant::now();
+    let mut window = idle_window(now);
+
+    // 比全局值短的规则不生效
+    record_debounce_event(&mut window, vec!["src/a.rs".to_string()], now, global, None, &overrides);
+    assert!(window.active_override.is_none());
+    assert_eq!(window.deadline, now + global);
+
+    record_debounce_event(&mut window, vec!["config.json".to_string()], now, global, None, &overrides);
+    assert_eq!(window.active_override.as_ref().unwrap().glob, "*.json");
+    assert_eq!(window.deadline, now + Duration::from_millis(3000));
+
+    // 重叠的规则取本轮中最长的一条，之后较短的匹配不会降低防抖时间
+    record_debounce_event(&mut window, vec!["schema.json".to_string()], now, global, None, &overrides);
+    record_debounce_event(&mut window, vec!["other.json".to_string()], now, global, None, &overrides);
+    assert_eq!(window.active_override.as_ref().unwrap().glob, "schema.json");
+    assert_eq!(window.deadline, now + Duration::from_millis(10000));
+
+    // 新的一轮重新计算
+    window.active = false;
+    record_debounce_event(&mut window, vec!["README.md".to_string()], now, global, None, &overrides);
+    assert!(window.active_override.is_none());
+    assert_eq!(window.deadline, now + global);
+}
+
+// ---------- 批量预览回退 ----------
+
+#[tokio::test]
+async fn rollback_previews_report_per_candidate_stats() {
+    let dir = repo_with_commits("preview-rollbacks", 3);
+    fs::write(dir.join("extra.txt"), "one\ntwo\n").unwrap();
+    git(&dir, &["add", "."]);
+    git(&dir, &["commit", "-qm", "extra"]);
+
+    let candidates = preview_rollbacks(
+        path_string(&dir),
+        vec!["HEAD~1".to_string(), "not-a-commit".to_string(), "HEAD~3".to_string()],
+    )
+    .await
+    .unwrap();
+    assert_eq!(candidates.len(), 3);
+
+    let to_previous = &candidates[0];
+    assert_eq!(to_previous.hash, "HEAD~1");
+    assert!(to_previous.stat.success);
+    assert_eq!((to_previous.stat.files, to_previous.stat.insertions, to_previous.stat.deletions), (1, 0, 2));
+
+    // 无效的哈希只影响自己的结果
+    assert!(!candidates[1].stat.success);
+    assert!(candidates[1].stat.error.is_some());
+
+    let to_first = &candidates[2].stat;
+    assert!(to_first.success);
+    assert_eq!((to_first.files, to_first.insertions, to_first.deletions), (2, 1, 3));
+}
+
+// ---------- 目录快照 ----------
+
+#[test]
+fn internal_paths_are_matched_by_component() {
+    let root = Path::new("/work/app");
+    assert!(is_internal_path(&root.join(".git/index"), root));
+    assert!(is_internal_path(&root.join("vendor/lib/.git/HEAD"), root));
+    assert!(is_internal_path(&root.join(".vibesnap/dir_snapshot_1.json"), root));
+    assert!(!is_internal_path(&root.join(".github/workflows/ci.yml"), root));
+    assert!(!is_internal_path(&root.join(".gitignore"), root));
+    assert!(!is_internal_path(&root.join("docs/.vibesnap-notes.md"), root));
+}
+
+#[test]
+fn unchanged_directory_listing_is_not_committed() {
+    let dir = repo_with_commits("dir-snapshot-unchanged", 1);
+    let first = record_directory_snapshot(&dir);
+    assert!(first.success && !first.no_changes, "{:?}", first.error);
+    let commits = git(&dir, &["rev-list", "--count", "HEAD"]);
+
+    let second = record_directory_snapshot(&dir);
+    assert!(second.success && second.no_changes);
+    assert_eq!(git(&dir, &["rev-list", "--count", "HEAD"]), commits);
+
+    // 目录列表变化后重新提交，且只提交快照文件
+    fs::write(dir.join("new.txt"), "new").unwrap();
+    std::thread::sleep(Duration::from_millis(1100));
+    let third = record_directory_snapshot(&dir);
+    assert!(third.success && !third.no_changes, "{:?}", third.error);
+    let committed = git(&dir, &["show", "--name-only", "--format=", "HEAD"]);
+    assert!(committed.trim().starts_with(".vibesnap/dir_snapshot_") && committed.lines().count() == 1, "{}", committed);
+}
+
+#[test]
+fn directory_snapshot_tasks_are_per_project() {
+    let first = temp_project("dir-snapshot-task-a");
+    let second = temp_project("dir-snapshot-task-b");
+    let (first, second) = (path_string(&first), path_string(&second));
+
+    let first_task = start_dir_snapshot_task(&first, 60);
+    let second_task = start_dir_snapshot_task(&second, 60);
+    assert!(dir_snapshot_task_active(&first, first_task));
+    assert!(dir_snapshot_task_active(&second, second_task));
+
+    // 同一项目重新启动（或停止）后旧任务退出
+    let restarted = start_dir_snapshot_task(&first, 60);
+    assert!(!dir_snapshot_task_active(&first, first_task));
+    assert!(dir_snapshot_task_active(&first, restarted));
+    assert!(dir_snapshot_task_active(&second, second_task));
+}
+
+// ---------- 子模块变更 ----------
+
+#[test]
+fn submodule_changes_come_from_gitlinks_in_one_diff() {
+    let dir = repo_with_commits("submodule_gitlinks", 1);
+    let target = git(&dir, &["rev-parse", "HEAD"]).trim().to_string();
+    git(&dir, &["update-index", "--add", "--cacheinfo", &format!("160000,{},vendor/lib", target)]);
+    git(&dir, &["commit", "-qm", "add submodule"]);
+
+    let added = snapshot_submodule_changes(&dir, "HEAD");
+    assert_eq!(added.len(), 1);
+    assert_eq!(added["vendor/lib"].old_commit, "");
+    assert_eq!(added["vendor/lib"].new_commit, target);
+
+    git(&dir, &["rm", "-q", "--cached", "vendor/lib"]);
+    git(&dir, &["commit", "-qm", "remove submodule"]);
+    let removed = snapshot_submodule_changes(&dir, "HEAD");
+    assert_eq!(removed["vendor/lib"].old_commit, target);
+    assert_eq!(removed["vendor/lib"].new_commit, "");
+
+    // 普通文件不算子模块
+    assert!(snapshot_submodule_changes(&dir, "HEAD~2").is_empty());
+}
+
+// ---------- 工作区指纹 ----------
+
+fn loose_object_count(dir: &Path) -> String {
+    git(dir, &["count-objects"]).split_whitespace().next().unwrap_or_default().to_string()
+}
+
+#[tokio::test]
+async fn working_tree_hash_does_not_write_objects() {
+    let dir = repo_with_commits("working_tree_hash", 1);
+    fs::write(dir.join(".gitignore"), "*.log\n").unwrap();
+    let objects_before = loose_object_count(&dir);
+
+    let initial = working_tree_hash(path_string(&dir)).await.unwrap();
+    assert_eq!(initial.len(), WORKING_TREE_HASH_LEN);
+    assert_eq!(initial, working_tree_hash(path_string(&dir)).await.unwrap());
+
+    fs::write(dir.join("file.txt"), "edited").unwrap();
+    let edited = working_tree_hash(path_string(&dir)).await.unwrap();
+    assert_ne!(initial, edited);
+
+    fs::write(dir.join("new.txt"), "untracked").unwrap();
+    let with_untracked = working_tree_hash(path_string(&dir)).await.unwrap();
+    assert_ne!(edited, with_untracked);
+
+    // 被忽略的文件不影响指纹
+    fs::write(dir.join("debug.log"), "ignored").unwrap();
+    assert_eq!(with_untracked, working_tree_hash(path_string(&dir)).await.unwrap());
+
+    fs::remove_file(dir.join("file.txt")).unwrap();
+    assert_ne!(with_untracked, working_tree_hash(path_string(&dir)).await.unwrap());
+
+    assert_eq!(loose_object_count(&dir), objects_before);
+    assert!(git(&dir, &["status", "--porcelain"]).contains("?? new.txt"));
+}
+
+// ---------- 按会话分组的历史记录 ----------
+
+#[tokio::test]
+async fn grouped_history_respects_limit() {
+    let dir = repo_with_commits("grouped_limit", 5);
+    let count = |sessions: Vec<SnapshotSession>| sessions.iter().map(|session| session.commit_count).sum::<usize>();
+
+    assert_eq!(count(get_snapshot_history_grouped(path_string(&dir), 60.0, Some(3)).await.unwrap()), 3);
+    assert_eq!(count(get_snapshot_history_grouped(path_string(&dir), 60.0, None).await.unwrap()), 5);
+}
+
+// ---------- 标签之间的差异 ----------
+
+#[tokio::test]
+async fn diff_tags_returns_one_result_per_file() {
+    let dir = init_repo("diff_tags_files");
+    fs::write(dir.join("a.txt"), "one\n").unwrap();
+    fs::write(dir.join("b.txt"), "moved content\n").unwrap();
+    git(&dir, &["add", "."]);
+    git(&dir, &["commit", "-qm", "v1"]);
+    git(&dir, &["tag", "v1"]);
+    fs::write(dir.join("a.txt"), "two\n").unwrap();
+    git(&dir, &["mv", "b.txt", "c.txt"]);
+    fs::write(dir.join("d.txt"), "new\n").unwrap();
+    git(&dir, &["add", "."]);
+    git(&dir, &["commit", "-qm", "v2"]);
+    git(&dir, &["tag", "-a", "v2", "-m", "two"]);
+
+    let result = diff_tags(path_string(&dir), "v1".to_string(), "v2".to_string(), None).await.unwrap();
+    assert!(result.success, "{:?}", result.error);
+    let files: Vec<(&str, &str)> = result.files.iter().map(|file| (file.path.as_str(), file.change_type.as_str())).collect();
+    assert_eq!(files, vec![("a.txt", "modified"), ("c.txt", "renamed"), ("d.txt", "added")]);
+    let modified: Vec<(&str, &str)> = result.files[0]
+        .diff
+        .lines
+        .iter()
+        .map(|line| (line.change_type.as_str(), line.content.as_str()))
+        .collect();
+    assert_eq!(modified, vec![("removed", "one"), ("added", "two")]);
+    assert_eq!(result.files[1].old_path.as_deref(), Some("b.txt"));
+    assert!(result.files[1].diff.lines.is_empty());
+
+    let single = diff_tags(path_string(&dir), "v1".to_string(), "v2".to_string(), Some("a.txt".to_string())).await.unwrap();
+    assert_eq!(single.files.len(), 1);
+
+    let same = diff_tags(path_string(&dir), "v2".to_string(), "v2".to_string(), None).await.unwrap();
+    assert!(same.files.is_empty() && same.summary.unwrap().contains("没有差异"));
+
+    let missing = diff_tags(path_string(&dir), "v1".to_string(), "nope".to_string(), None).await.unwrap();
+    assert_eq!(missing.error.as_deref(), Some("标签不存在: nope"));
+}
+
+// ---------- 快照报告（golden 文件） ----------
+
+// 提交时间、作者和内容都固定的快照，报告内容（包括哈希）每次都相同
+fn report_fixture_repo(name: &str) -> PathBuf {
+    let dir = init_repo(name);
+    fs::write(dir.join("app.js"), "const a = 1;\nconst b = 2;\nconst c = 3;\n").unwrap();
+    fs::write(dir.join("old_name.txt"), "renamed content\n").unwrap();
+    fs::write(dir.join("obsolete.txt"), "gone\n").unwrap();
+    git(&dir, &["add", "."]);
+    commit_at(&dir, "initial", 1_700_000_000);
+
+    fs::write(dir.join("app.js"), "const a = 1;\nconst b = 20;\nconst c = 3;\nconst d = 4;\n").unwrap();
+    git(&dir, &["mv", "old_name.txt", "new_name.txt"]);
+    git(&dir, &["rm", "-q", "obsolete.txt"]);
+    fs::write(dir.join("README_*.md"), "Run ```npm test``` first\n").unwrap();
+    git(&dir, &["add", "."]);
+    commit_at(
+        &dir,
+        &format!("{}把 b 改成 20 并添加 d\n\n{}", VIBE_COMMIT_PREFIX, AUTO_SNAPSHOT_TRAILER),
+        1_700_000_600,
+    );
+    // 未跟踪的 AI 工具特征文件，报告中的工具一行据此推测
+    fs::write(dir.join(".cursorrules"), "").unwrap();
+    dir
+}
+
+async fn render_report(dir: &Path, include_diffs: bool, max_lines: usize) -> (SnapshotReportResult, String) {
+    let output = dir.join("report.md");
+    let result = export_snapshot_report(path_string(dir), "HEAD".to_string(), path_string(&output), include_diffs, max_lines)
+        .await
+        .unwrap();
+    assert!(result.success, "{:?}", result.error);
+    let report = fs::read_to_string(&output).unwrap();
+    (result, report)
+}
+
+fn assert_golden(report: &str, golden_name: &str, golden: &str) {
+    if report != golden {
+        let actual = std::env::temp_dir().join(golden_name);
+        fs::write(&actual, report).unwrap();
+        panic!("报告与 testdata/{} 不一致，实际内容已写入 {}", golden_name, actual.display());
+    }
+}
+
+#[tokio::test]
+async fn snapshot_report_with_diffs_matches_golden() {
+    let dir = report_fixture_repo("report_golden_diffs");
+    let (result, report) = render_report(&dir, true, 3).await;
+    assert_eq!(result.truncated_files, vec!["app.js".to_string()]);
+    assert_golden(&report, "snapshot_report_diffs.md", include_str!("testdata/snapshot_report_diffs.md"));
+}
+
+#[tokio::test]
+async fn snapshot_report_summary_matches_golden() {
+    let dir = report_fixture_repo("report_golden_summary");
+    let (result, report) = render_report(&dir, false, 0).await;
+    assert!(result.truncated_files.is_empty());
+    assert_golden(&report, "snapshot_report_summary.md", include_str!("testdata/snapshot_report_summary.md"));
+}
+
+// ---------- 定时提交 ----------
+
+#[tokio::test]
+async fn periodic_commits_stop_with_their_session() {
+    let (_window, mut stop_rx, stop_tx) = register_test_session("/periodic/session");
+    let mut ticker = tokio::time::interval(Duration::from_millis(10));
+    ticker.tick().await;
+    assert!(wait_for_periodic_tick(&mut ticker, &mut stop_rx).await);
+
+    // 间隔很长时，停止信号也能立即结束等待
+    let mut slow_ticker = tokio::time::interval(Duration::from_secs(3600));
+    slow_ticker.tick().await;
+    let waiting = tokio::spawn(async move { wait_for_periodic_tick(&mut slow_ticker, &mut stop_rx).await });
+    stop_watcher_sessions(Some("/periodic/session"));
+    let continued = tokio::time::timeout(Duration::from_secs(5), waiting).await.unwrap().unwrap();
+    assert!(!continued);
+    assert!(*stop_tx.borrow());
+}
+
+#[tokio::test]
+async fn periodic_commits_wait_for_idle_user() {
+    let _guard = USER_ACTIVITY_TEST_LOCK.lock().await;
+    let mut config = watcher_config(Path::new("/periodic/idle"));
+    report_activity().await.unwrap();
+
+    config.user_idle_duration = None;
+    assert!(!user_still_active(&config));
+    config.user_idle_duration = Some(60_000);
+    assert!(user_still_active(&config));
+    config.user_idle_duration = Some(0);
+    assert!(!user_still_active(&config));
+}
+
+// ---------- 初始提交与无变化的文件差异 ----------
+
+#[tokio::test]
+async fn file_diff_content_flags_root_commit_with_short_hash() {
+    let dir = repo_with_commits("file_diff_root", 2);
+    let root = git(&dir, &["rev-list", "--max-parents=0", "HEAD"]).trim()[..7].to_string();
+
+    let diff = get_file_diff_content(path_string(&dir), root, "file.txt".to_string(), None, None).await.unwrap();
+
+    assert!(diff.success, "{:?}", diff.error);
+    assert!(diff.is_initial && !diff.no_changes);
+    let content = diff.diff_content.unwrap();
+    assert!(content.contains("--- /dev/null") && content.contains("+v1"), "{}", content);
+}
+
+#[tokio::test]
+async fn file_diff_content_flags_unchanged_file() {
+    let dir = repo_with_commits("file_diff_unchanged", 1);
+    fs::write(dir.join("other.txt"), "other").unwrap();
+    git(&dir, &["add", "."]);
+    git(&dir, &["commit", "-qm", "other"]);
+    let head = git(&dir, &["rev-parse", "--short=7", "HEAD"]).trim().to_string();
+
+    let diff = get_file_diff_content(path_string(&dir), head, "file.txt".to_string(), None, None).await.unwrap();
+
+    assert!(diff.success, "{:?}", diff.error);
+    assert!(diff.no_changes && !diff.is_initial);
+    assert!(!diff.diff_content.unwrap_or_default().contains("文件内容"));
+}
+
+// ---------- 短哈希 ----------
+
+#[test]
+fn short_hash_never_slices_past_the_end() {
+    assert_eq!(short_hash("0123456789abcdef"), "01234567");
+    assert_eq!(short_hash("0123456"), "0123456");
+    assert_eq!(short_hash(""), "");
+    assert_eq!(short_hash("快照快照快照快照快照"), "快照快照快照快照");
+}
+
+#[tokio::test]
+async fn diff_commands_accept_seven_char_hashes() {
+    let dir = repo_with_commits("seven_char_hash", 1);
+    fs::write(dir.join("other.txt"), "other").unwrap();
+    git(&dir, &["add", "."]);
+    git(&dir, &["commit", "-qm", "other"]);
+    let head = git(&dir, &["rev-parse", "--short=7", "HEAD"]).trim().to_string();
+    assert_eq!(head.len(), 7);
+
+    let raw = get_file_diff_content(path_string(&dir), head.clone(), "file.txt".to_string(), None, None).await.unwrap();
+    assert!(raw.success && raw.no_changes, "{:?}", raw.error);
+
+    let friendly = get_friendly_diff_content(path_string(&dir), head, "file.txt".to_string(), None).await.unwrap();
+    assert!(friendly.success, "{:?}", friendly.error);
+    assert!(friendly.lines.iter().all(|line| line.change_type == "unchanged"));
+}
+
+// ---------- 提交消息禁止模式 ----------
+
+// 禁止模式和应用配置路径是全局状态，相关测试串行执行
+static APP_CONFIG_TEST_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+#[tokio::test]
+async fn banned_patterns_persist_in_app_config() {
+    let _guard = APP_CONFIG_TEST_LOCK.lock().await;
+    let dir = temp_project("banned_patterns_config");
+    let config_file = dir.join("config").join(APP_CONFIG_FILE);
+    init_app_config(config_file.clone());
+
+    assert!(set_banned_message_patterns(vec!["(".to_string()]).await.is_err());
+    set_banned_message_patterns(vec![r"sk-[A-Za-z0-9]{8,}".to_string()]).await.unwrap();
+    assert!(fs::read_to_string(&config_file).unwrap().contains("sk-[A-Za-z0-9]{8,}"));
+
+    // 模拟重新启动：清空内存中的模式后重新载入配置
+    BANNED_MESSAGE_PATTERNS.lock().unwrap().clear();
+    init_app_config(config_file);
+    assert_eq!(get_banned_message_patterns().await.unwrap(), vec![r"sk-[A-Za-z0-9]{8,}".to_string()]);
+    assert!(check_banned_message("key sk-abcdef123456").unwrap_err().contains("sk-[A-Za-z0-9]{8,}"));
+
+    set_banned_message_patterns(vec![]).await.unwrap();
+    *APP_CONFIG_PATH.lock().unwrap() = None;
+}
+
+#[tokio::test]
+async fn unparseable_app_config_is_backed_up_instead_of_overwritten() {
+    let _guard = APP_CONFIG_TEST_LOCK.lock().await;
+    let dir = temp_project("app_config_corrupt");
+    let config_file = dir.join(APP_CONFIG_FILE);
+    fs::write(&config_file, "{\"banned_message_patterns\": [\"sk-").unwrap();
+
+    init_app_config(config_file.clone());
+    let backups: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().contains(".bad-"))
+        .collect();
+    assert_eq!(backups.len(), 1);
+    assert_eq!(fs::read_to_string(backups[0].path()).unwrap(), "{\"banned_message_patterns\": [\"sk-");
+
+    // 运行期间配置文件损坏时，写入报错而不是用默认值覆盖
+    fs::write(&config_file, "not json").unwrap();
+    assert!(load_app_config().is_err());
+    assert!(set_banned_message_patterns(vec!["x".to_string()]).await.is_err());
+    assert_eq!(fs::read_to_string(&config_file).unwrap(), "not json");
+
+    *APP_CONFIG_PATH.lock().unwrap() = None;
+    BANNED_MESSAGE_PATTERNS.lock().unwrap().clear();
+}
+
+#[tokio::test]
+async fn concurrent_app_config_updates_are_not_lost() {
+    let _guard = APP_CONFIG_TEST_LOCK.lock().await;
+    let dir = temp_project("app_config_concurrent");
+    let config_file = dir.join(APP_CONFIG_FILE);
+    init_app_config(config_file.clone());
+
+    let handles: Vec<_> = (0..16)
+        .map(|i| {
+            std::thread::spawn(move || {
+                update_app_config(|config| config.banned_message_patterns.push(format!("pattern-{}", i))).unwrap();
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let saved = load_app_config().unwrap();
+    assert_eq!(saved.banned_message_patterns.len(), 16);
+    assert!(!config_file.with_extension("json.tmp").exists());
+
+    *APP_CONFIG_PATH.lock().unwrap() = None;
+}
+
+#[tokio::test]
+async fn auto_commit_rejects_banned_message_before_staging() {
+    let _guard = APP_CONFIG_TEST_LOCK.lock().await;
+    let dir = repo_with_commits("banned_auto_commit", 1);
+    let log_file = dir.join("prompts.log");
+    fs::write(&log_file, "use token banned-702-token\n").unwrap();
+    fs::write(dir.join("file.txt"), "changed").unwrap();
+    let mut config = watcher_config(&dir);
+    config.log_file_path = Some(path_string(&log_file));
+
+    set_banned_message_patterns(vec!["banned-702-token".to_string()]).await.unwrap();
+    let result = auto_commit_changes(&SystemGitRunner, &config).await;
+    set_banned_message_patterns(vec![]).await.unwrap();
+
+    let result = result.unwrap();
+    assert!(!result.success);
+    assert!(result.error.unwrap().contains("banned-702-token"));
+    assert!(git(&dir, &["diff", "--cached", "--name-only"]).is_empty());
+    assert_eq!(git(&dir, &["rev-list", "--count", "HEAD"]).trim(), "1");
+}
+
+// ---------- 行范围历史 ----------
+
+#[tokio::test]
+async fn line_range_history_lists_commits_touching_the_range() {
+    let dir = init_repo("line_range_history");
+    fs::write(dir.join("lib.rs"), "fn a() {\n    1\n}\nfn b() {\n    2\n}\n").unwrap();
+    git(&dir, &["add", "."]);
+    git(&dir, &["commit", "-qm", "add functions"]);
+    fs::write(dir.join("lib.rs"), "fn a() {\n    10\n}\nfn b() {\n    2\n}\n").unwrap();
+    git(&dir, &["commit", "-qam", "change a"]);
+    fs::write(dir.join("lib.rs"), "fn a() {\n    10\n}\nfn b() {\n    20\n}\n").unwrap();
+    git(&dir, &["commit", "-qam", "change b"]);
+
+    let history = line_range_history(path_string(&dir), "lib.rs".to_string(), 1, 3).await.unwrap();
+    assert!(history.success, "{:?}", history.error);
+    let messages: Vec<&str> = history.commits.iter().map(|commit| commit.message.as_str()).collect();
+    assert_eq!(messages, vec!["change a", "add functions"]);
+    assert!(history.commits[0].patch.contains("-    1") && history.commits[0].patch.contains("+    10"));
+    assert_eq!(history.commits[0].full_hash.len(), 40);
+
+    let invalid = line_range_history(path_string(&dir), "lib.rs".to_string(), 3, 1).await.unwrap();
+    assert_eq!(invalid.error.as_deref(), Some("无效的行范围: 3-1"));
+    let too_long = line_range_history(path_string(&dir), "lib.rs".to_string(), 1, 99).await.unwrap();
+    assert!(too_long.error.unwrap().contains("共 6 行"));
+    let missing = line_range_history(path_string(&dir), "missing.rs".to_string(), 1, 1).await.unwrap();
+    assert!(missing.error.unwrap().contains("missing.rs"));
+}
+
+// ---------- 未出生 HEAD 的已有仓库 ----------
+
+#[tokio::test]
+async fn ensure_git_repo_commits_into_unborn_existing_repo() {
+    let dir = init_repo("ensure_unborn_repo");
+    fs::write(dir.join("main.txt"), "hello").unwrap();
+
+    let result = ensure_git_repo(path_string(&dir), None, None, None, None, None).await.unwrap();
+    assert!(result.success, "{:?}", result.error);
+    assert!(result.existing_repo.is_none());
+    assert_eq!(git(&dir, &["rev-list", "--count", "HEAD"]).trim(), "1");
+    assert!(git(&dir, &["ls-files"]).lines().any(|line| line == "main.txt"));
+    assert_eq!(git(&dir, &["symbolic-ref", "--short", "HEAD"]).trim(), "master");
+    assert_eq!(git(&dir, &["log", "-1", "--format=%ae"]).trim(), "tester@example.com");
+
+    let again = ensure_git_repo(path_string(&dir), None, None, None, None, None).await.unwrap();
+    assert!(again.success);
+    assert!(again.existing_repo.is_some());
+    assert_eq!(git(&dir, &["rev-list", "--count", "HEAD"]).trim(), "1");
+}
+
+// ---------- 按路径 glob 过滤历史 ----------
+
+#[tokio::test]
+async fn history_path_pattern_uses_literal_separator_globs() {
+    let dir = init_repo("history_path_pattern");
+    for (path, message) in [("src/lib.rs", "top level"), ("src/ui/view.rs", "nested"), ("docs/guide.md", "docs")] {
+        let file = dir.join(path);
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(&file, message).unwrap();
+        git(&dir, &["add", "."]);
+        git(&dir, &["commit", "-qm", message]);
+    }
+    let messages = |history: SnapshotHistory| -> Vec<String> {
+        assert!(history.success, "{:?}", history.error);
+        history.history.into_iter().map(|item| item.message).collect()
+    };
+
+    let deep = get_snapshot_history(path_string(&dir), None, None, None, Some("src/**/*.rs".to_string()), None).await.unwrap();
+    assert_eq!(messages(deep), vec!["nested", "top level"]);
+    let shallow = get_snapshot_history(path_string(&dir), None, None, None, Some("src/*.rs".to_string()), None).await.unwrap();
+    assert_eq!(messages(shallow), vec!["top level"]);
+
+    let invalid = get_snapshot_history(path_string(&dir), None, None, None, Some("src/[".to_string()), None).await.unwrap();
+    assert!(!invalid.success);
+    assert!(invalid.error.unwrap().contains("无效的路径匹配规则"));
+}
+
+// ---------- 撤销最近一次快照 ----------
+
+#[tokio::test]
+async fn undo_last_snapshot_keeps_changes_staged() {
+    let dir = repo_with_commits("undo_last_snapshot", 2);
+    let first = git(&dir, &["rev-parse", "HEAD~1"]);
+
+    let result = undo_snapshot(path_string(&dir)).await.unwrap();
+    assert!(result.success, "{:?}", result.error);
+    assert_eq!(git(&dir, &["rev-parse", "HEAD"]), first);
+    assert_eq!(git(&dir, &["diff", "--cached", "--name-only"]).trim(), "file.txt");
+    assert_eq!(fs::read_to_string(dir.join("file.txt")).unwrap(), "v2");
+    assert!(in_watcher_quiet_window(&path_string(&dir)));
+}
+
+#[tokio::test]
+async fn undo_last_snapshot_refuses_root_and_merge_commits() {
+    let root = repo_with_commits("undo_last_snapshot_root", 1);
+    let result = undo_snapshot(path_string(&root)).await.unwrap();
+    assert!(!result.success);
+    assert!(result.error.unwrap().contains("第一个提交"));
+    assert_eq!(git(&root, &["rev-list", "--count", "HEAD"]).trim(), "1");
+
+    let dir = repo_with_commits("undo_last_snapshot_merge", 1);
+    git(&dir, &["checkout", "-qb", "side"]);
+    fs::write(dir.join("side.txt"), "side").unwrap();
+    git(&dir, &["add", "."]);
+    git(&dir, &["commit", "-qm", "side"]);
+    git(&dir, &["checkout", "-q", "master"]);
+    fs::write(dir.join("main.txt"), "main").unwrap();
+    git(&dir, &["add", "."]);
+    git(&dir, &["commit", "-qm", "main"]);
+    git(&dir, &["merge", "-q", "--no-edit", "side"]);
+    let head = git(&dir, &["rev-parse", "HEAD"]);
+
+    let result = undo_snapshot(path_string(&dir)).await.unwrap();
+    assert!(!result.success);
+    assert!(result.error.unwrap().contains("合并提交"));
+    assert_eq!(git(&dir, &["rev-parse", "HEAD"]), head);
+}
+
+// ---------- 提交回声 ----------
+
+#[cfg(unix)]
+#[test]
+fn commit_echoes_match_by_repo_relative_path() {
+    let dir = repo_with_commits("commit_echo_relative", 1);
+    let other = repo_with_commits("commit_echo_other", 1);
+    let link = std::env::temp_dir().join("vibesnap-tests").join("commit_echo_link");
+    let _ = fs::remove_file(&link);
+    std::os::unix::fs::symlink(&dir, &link).unwrap();
+
+    record_commit_echoes(&SystemGitRunner, &dir);
+    assert!(is_commit_echo(&path_string(&dir), &dir.join("file.txt")));
+    assert!(is_commit_echo(&path_string(&link), &dir.join("file.txt")));
+    assert!(is_commit_echo(&path_string(&dir), &link.join("./file.txt")));
+    assert!(!is_commit_echo(&path_string(&other), &other.join("file.txt")));
+    assert!(!is_commit_echo(&path_string(&dir), &dir.join("missing.txt")));
+
+    // 记录之后再次修改的文件不再是回声
+    let file = fs::OpenOptions::new().write(true).open(dir.join("file.txt")).unwrap();
+    file.set_modified(std::time::SystemTime::now() + Duration::from_secs(60)).unwrap();
+    assert!(!is_commit_echo(&path_string(&dir), &dir.join("file.txt")));
+}
+
+// ---------- 快照摘要邮件 ----------
+
+fn digest_config(schedule: Option<&str>) -> EmailDigestConfig {
+    EmailDigestConfig {
+        smtp_host: "smtp.example.com".to_string(),
+        smtp_port: 587,
+        username: "tester@example.com".to_string(),
+        password: "secret".to_string(),
+        password_ref: None,
+        recipient: "tester@example.com".to_string(),
+        schedule: schedule.map(str::to_string),
+    }
+}
+
+#[test]
+fn snapshot_digest_lists_only_escaped_snapshots() {
+    let dir = repo_with_commits("snapshot_digest", 1);
+    fs::write(dir.join("file.txt"), "snapshot").unwrap();
+    git(&dir, &["commit", "-qam", &format!("{}fix <b>bold</b> & more", VIBE_COMMIT_PREFIX)]);
+    fs::write(dir.join("file.txt"), "manual").unwrap();
+    git(&dir, &["commit", "-qam", "manual commit"]);
+
+    let html = build_snapshot_digest(&dir).unwrap().unwrap();
+    assert!(html.contains("共 1 个快照"));
+    assert!(html.contains("fix &lt;b&gt;bold&lt;/b&gt; &amp; more"));
+    assert!(!html.contains("manual commit") && !html.contains("commit 1"));
+
+    let empty = repo_with_commits("snapshot_digest_empty", 2);
+    assert!(build_snapshot_digest(&empty).unwrap().is_none());
+}
+
+#[tokio::test]
+async fn email_digest_schedule_persists_per_project() {
+    let _guard = APP_CONFIG_TEST_LOCK.lock().await;
+    let dir = repo_with_commits("email_digest_schedule", 1);
+    let config_file = temp_project("email_digest_config").join(APP_CONFIG_FILE);
+    init_app_config(config_file.clone());
+    let key = project_key(&path_string(&dir));
+
+    let invalid = watch_auto_commit_summary_email(path_string(&dir), Some(digest_config(Some("not a cron")))).await;
+    assert!(invalid.err().unwrap().contains("not a cron"));
+    assert!(load_app_config().unwrap().email_digests.is_empty());
+
+    let result = watch_auto_commit_summary_email(path_string(&dir), Some(digest_config(Some("0 30 18 * * *")))).await.unwrap();
+    assert!(result.success);
+    let saved = load_app_config().unwrap().email_digests;
+    assert_eq!(saved[&key].schedule.as_deref(), Some("0 30 18 * * *"));
+    assert!(EMAIL_DIGEST_SCHEDULERS.lock().await.as_ref().unwrap().contains_key(&key));
+
+    // 密码只保存在钥匙串中，配置文件只有当前用户可读
+    let password_ref = saved[&key].password_ref.clone().unwrap();
+    assert_eq!(keychain_get(&password_ref).unwrap(), "secret");
+    assert!(!fs::read_to_string(&config_file).unwrap().contains("secret"));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(fs::metadata(&config_file).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+
+    // 之后修改设置时可以不再传入密码
+    let mut without_password = digest_config(None);
+    without_password.password = String::new();
+    assert!(watch_auto_commit_summary_email(path_string(&dir), Some(without_password)).await.unwrap().success);
+    assert_eq!(load_app_config().unwrap().email_digests[&key].password_ref.as_deref(), Some(password_ref.as_str()));
+    assert_eq!(keychain_get(&password_ref).unwrap(), "secret");
+
+    let stopped = watch_auto_commit_summary_email(path_string(&dir), None).await.unwrap();
+    assert!(stopped.success);
+    assert!(load_app_config().unwrap().email_digests.is_empty());
+    assert!(!EMAIL_DIGEST_SCHEDULERS.lock().await.as_ref().unwrap().contains_key(&key));
+    assert!(keychain_get(&password_ref).is_err());
+
+    *APP_CONFIG_PATH.lock().unwrap() = None;
+}
+
+#[tokio::test]
+async fn plaintext_digest_password_moves_to_the_keychain() {
+    let _guard = APP_CONFIG_TEST_LOCK.lock().await;
+    let dir = repo_with_commits("email_digest_migrate", 1);
+    let config_file = temp_project("email_digest_migrate_config").join(APP_CONFIG_FILE);
+    let key = project_key(&path_string(&dir));
+    let mut legacy = serde_json::to_value(digest_config(None)).unwrap();
+    legacy["password"] = serde_json::json!("old-secret");
+    fs::write(&config_file, serde_json::json!({ "email_digests": { key.clone(): legacy } }).to_string()).unwrap();
+
+    init_app_config(config_file.clone());
+    restore_email_digests().await;
+
+    let saved = load_app_config().unwrap().email_digests;
+    assert_eq!(keychain_get(saved[&key].password_ref.as_deref().unwrap()).unwrap(), "old-secret");
+    assert!(!fs::read_to_string(&config_file).unwrap().contains("old-secret"));
+
+    stop_email_digest(&key).await;
+    *APP_CONFIG_PATH.lock().unwrap() = None;
+}
+
+// ---------- 友好差异的新旧行号 ----------
+
+const MULTI_HUNK_DIFF: &str = "diff --git a/a.txt b/a.txt
+index 1111111..2222222 100644
+--- a/a.txt
++++ b/a.txt
+@@ -1,3 +1,4 @@
+ one
++inserted
+ two
+ three
+@@ -10,4 +11,3 @@ fn section()
+ ten
+-eleven
+-twelve
++twelve!
+ thirteen
+";
+
+#[test]
+fn friendly_diff_numbers_old_and_new_columns_across_hunks() {
+    let friendly = parse_friendly_diff(MULTI_HUNK_DIFF);
+    let columns: Vec<(&str, &str, Option<usize>, Option<usize>)> = friendly
+        .lines
+        .iter()
+        .map(|line| (line.change_type.as_str(), line.content.as_str(), line.old_line_number, line.new_line_number))
+        .collect();
+    assert_eq!(
+        columns,
+        vec![
+            ("unchanged", " one", Some(1), Some(1)),
+            ("added", "inserted", None, Some(2)),
+            ("unchanged", " two", Some(2), Some(3)),
+            ("unchanged", " three", Some(3), Some(4)),
+            ("unchanged", " ten", Some(10), Some(11)),
+            ("removed", "eleven", Some(11), None),
+            ("removed", "twelve", Some(12), None),
+            ("added", "twelve!", None, Some(12)),
+            ("unchanged", " thirteen", Some(13), Some(13)),
+        ]
+    );
+    assert_eq!(friendly.lines[0].hunk_range.as_deref(), Some("@@ -1,3 +1,4 @@"));
+    assert_eq!(friendly.lines[4].hunk_range.as_deref(), Some("@@ -10,4 +11,3 @@"));
+}
+
+// ---------- 项目路径重新映射 ----------
+
+#[tokio::test]
+async fn project_remap_tells_moved_repo_from_sibling_clone() {
+    let _guard = APP_CONFIG_TEST_LOCK.lock().await;
+    init_app_config(temp_project("project_remap_config").join(APP_CONFIG_FILE));
+    let parent = temp_project("project_remap");
+    let original = parent.join("app");
+    fs::create_dir_all(&original).unwrap();
+    git(&original, &["init", "-q", "-b", "master"]);
+    git(&original, &["config", "user.name", "tester"]);
+    git(&original, &["config", "user.email", "tester@example.com"]);
+    fs::write(original.join("file.txt"), "v1").unwrap();
+    git(&original, &["add", "."]);
+    git(&original, &["commit", "-qm", "init"]);
+
+    let recent = add_recent_project(path_string(&original)).await.unwrap();
+    let identity = recent[0].identity.clone().unwrap();
+    assert_eq!(get_project_identity(path_string(&original)).await.unwrap().repository_id, identity.repository_id);
+    git(&parent, &["clone", "-q", "app", "clone"]);
+    let clone = parent.join("clone");
+
+    // 原路径仍然存在时，另一个克隆不是同一个项目
+    let (result, _) = remap_project(path_string(&original), path_string(&clone)).await.unwrap();
+    assert!(!result.success);
+    assert!(validate_project_path(path_string(&original)).await.unwrap().valid);
+
+    let moved = parent.join("app-v2");
+    fs::rename(&original, &moved).unwrap();
+    let validation = validate_project_path(path_string(&original)).await.unwrap();
+    assert!(!validation.valid && !validation.exists);
+    assert_eq!(validation.suggestions, vec![path_string(&moved)]);
+
+    let (result, _) = remap_project(path_string(&original), path_string(&clone)).await.unwrap();
+    assert!(!result.success);
+    assert!(result.error.unwrap().contains("另一个克隆"));
+    let (result, _) = remap_project(path_string(&original), path_string(&moved)).await.unwrap();
+    assert!(result.success, "{:?}", result.error);
+    assert_eq!(result.root_commit, identity.root_commit);
+
+    // 最近项目指向新路径，原路径不再有身份信息可用于校验
+    let recent = get_recent_projects().await.unwrap();
+    assert_eq!(recent.len(), 1);
+    assert_eq!(recent[0].path, path_string(&moved));
+    let (result, _) = remap_project(path_string(&original), path_string(&moved)).await.unwrap();
+    assert!(!result.success);
+    assert!(validate_project_path(path_string(&original)).await.unwrap().suggestions.is_empty());
+
+    *APP_CONFIG_PATH.lock().unwrap() = None;
+}
+
+#[tokio::test]
+async fn project_remap_falls_back_to_root_commit_without_repository_id() {
+    let _guard = APP_CONFIG_TEST_LOCK.lock().await;
+    init_app_config(temp_project("project_remap_root_config").join(APP_CONFIG_FILE));
+    let parent = temp_project("project_remap_root");
+    let original = parent.join("app");
+    fs::rename(repo_with_commits("project_remap_root_repo", 1), &original).unwrap();
+    let other = parent.join("other");
+    fs::rename(init_repo("project_remap_root_other"), &other).unwrap();
+    fs::write(other.join("other.txt"), "unrelated").unwrap();
+    git(&other, &["add", "."]);
+    git(&other, &["commit", "-qm", "unrelated"]);
+
+    // 读取身份不会写入仓库配置
+    let config_before = fs::read_to_string(original.join(".git/config")).unwrap();
+    let identity = get_project_identity(path_string(&original)).await.unwrap();
+    assert!(identity.repository_id.is_none());
+    assert_eq!(fs::read_to_string(original.join(".git/config")).unwrap(), config_before);
+
+    // 最近项目中只记录了根提交（例如写入仓库标识失败）
+    update_app_config(|config| {
+        config.recent_projects.push(RecentProject {
+            path: path_string(&original),
+            identity: Some(identity.clone()),
+            last_opened: Local::now().to_rfc3339_opts(SecondsFormat::Secs, false),
+        });
+    })
+    .unwrap();
+
+    let moved = parent.join("app-v2");
+    fs::rename(&original, &moved).unwrap();
+    let validation = validate_project_path(path_string(&original)).await.unwrap();
+    assert_eq!(validation.suggestions, vec![path_string(&moved)]);
+
+    let (result, _) = remap_project(path_string(&original), path_string(&other)).await.unwrap();
+    assert!(!result.success);
+    assert!(result.error.unwrap().contains("根提交"));
+    let (result, _) = remap_project(path_string(&original), path_string(&moved)).await.unwrap();
+    assert!(result.success, "{:?}", result.error);
+    assert_eq!(result.root_commit, identity.root_commit);
+
+    *APP_CONFIG_PATH.lock().unwrap() = None;
+}
+
+#[tokio::test]
+async fn project_remap_moves_per_project_state_to_the_new_key() {
+    let _guard = APP_CONFIG_TEST_LOCK.lock().await;
+    let parent = temp_project("project_remap_state");
+    let original = parent.join("app");
+    fs::rename(repo_with_commits("project_remap_state_repo", 1), &original).unwrap();
+    init_app_config(temp_project("project_remap_state_config").join(APP_CONFIG_FILE));
+    let old_key = project_key(&path_string(&original));
+    add_recent_project(path_string(&original)).await.unwrap();
+    set_max_file_size(path_string(&original), Some(5.0)).await.unwrap();
+
+    assert!(watch_auto_commit_summary_email(path_string(&original), Some(digest_config(Some("0 30 18 * * *")))).await.unwrap().success);
+    let password_ref = load_app_config().unwrap().email_digests[&old_key].password_ref.clone().unwrap();
+    let (_window, stop_rx, _) = register_test_session(&path_string(&original));
+    start_dir_snapshot_task(&path_string(&original), 60);
+    begin_watcher_quiet_window(&path_string(&original));
+
+    let moved = parent.join("app-v2");
+    fs::rename(&original, &moved).unwrap();
+    let new_key = project_key(&path_string(&moved));
+    let (result, watcher) = remap_project(path_string(&original), path_string(&moved)).await.unwrap();
+    assert!(result.success, "{:?}", result.error);
+
+    // 摘要邮件的配置和定时任务都在新键下，钥匙串条目沿用
+    let config = load_app_config().unwrap();
+    assert!(!config.email_digests.contains_key(&old_key));
+    assert_eq!(config.email_digests[&new_key].password_ref.as_deref(), Some(password_ref.as_str()));
+    let schedulers = EMAIL_DIGEST_SCHEDULERS.lock().await.as_ref().unwrap().keys().cloned().collect::<HashSet<_>>();
+    assert!(schedulers.contains(&new_key));
+    assert!(!schedulers.contains(&old_key));
+
+    // 项目设置和最近项目也迁移到新路径
+    assert!(!config.project_settings.contains_key(&old_key));
+    assert_eq!(get_max_file_size(path_string(&moved)).await.unwrap(), Some(5.0));
+    assert_eq!(config.recent_projects.iter().map(|project| project.path.clone()).collect::<Vec<_>>(), vec![path_string(&moved)]);
+
+    // 原路径的监听停止，返回的配置指向新路径
+    assert!(*stop_rx.borrow());
+    assert!(watcher_session(&path_string(&original)).is_none());
+    assert_eq!(watcher.unwrap().project_path, path_string(&moved));
+
+    let tasks = DIR_SNAPSHOT_TASKS.get().unwrap().lock().unwrap().clone();
+    assert!(!tasks.contains_key(&old_key));
+    assert_eq!(tasks[&new_key].1, 60);
+    assert!(in_watcher_quiet_window(&path_string(&moved)));
+
+    start_dir_snapshot_task(&path_string(&moved), 0);
+    stop_email_digest(&path_string(&moved)).await;
+    *APP_CONFIG_PATH.lock().unwrap() = None;
+}
+
+// ---------- 暂存内容密钥扫描 ----------
+
+#[test]
+fn secret_scan_skips_content_that_looks_like_a_file_header() {
+    let dir = repo_with_commits("secret_scan_headers", 1);
+    fs::write(dir.join("notes.md"), "++ b/fake.txt\npassword = hunter2\n").unwrap();
+    fs::write(dir.join("密钥.txt"), "api_key = abc\n").unwrap();
+    git(&dir, &["add", "."]);
+
+    let found: Vec<(String, usize, String)> = scan_staged_secrets(&SystemGitRunner, &dir)
+        .into_iter()
+        .map(|secret| (secret.file, secret.line, secret.pattern_matched))
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            ("notes.md".to_string(), 2, "password".to_string()),
+            ("密钥.txt".to_string(), 1, "api_key".to_string()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn auto_commit_scans_staged_secrets() {
+    let dir = repo_with_commits("secret_scan_auto_commit", 1);
+    fs::write(dir.join("file.txt"), "secret = s3cr3t\n").unwrap();
+    let mut config = watcher_config(&dir);
+
+    config.enforce_no_secrets = true;
+    let rejected = auto_commit_changes(&SystemGitRunner, &config).await.unwrap();
+    assert!(!rejected.success);
+    assert_eq!(rejected.secret_scan_result.unwrap().len(), 1);
+    assert_eq!(git(&dir, &["rev-list", "--count", "HEAD"]).trim(), "1");
+
+    config.enforce_no_secrets = false;
+    let warned = auto_commit_changes(&SystemGitRunner, &config).await.unwrap();
+    assert!(warned.success, "{:?}", warned.error);
+    assert!(warned.message.contains("1 处疑似密钥"));
+    assert_eq!(warned.secret_scan_result.unwrap()[0].file, "file.txt");
+    assert_eq!(git(&dir, &["rev-list", "--count", "HEAD"]).trim(), "2");
+}
+
+// ---------- 快照后自动推送 ----------
+
+#[tokio::test]
+async fn auto_push_sends_head_to_bare_remote() {
+    let dir = repo_with_commits("auto_push_local", 2);
+    let remote = temp_project("auto_push_remote");
+    git(&remote, &["init", "-q", "--bare"]);
+    git(&dir, &["remote", "add", "origin", &path_string(&remote)]);
+
+    let status = run_auto_push(&SystemGitRunner, path_string(&dir)).await.unwrap();
+    assert!(status.success, "{:?}", status.error);
+    assert_eq!(status.remote.as_deref(), Some("origin"));
+    assert_eq!(status.branch.as_deref(), Some("master"));
+    assert_eq!(git(&remote, &["rev-parse", "refs/heads/master"]), git(&dir, &["rev-parse", "HEAD"]));
+    assert!(!AUTO_PUSH_RUNNING.lock().unwrap().as_ref().unwrap().contains(&project_key(&path_string(&dir))));
+}
+
+#[tokio::test]
+async fn auto_push_skips_while_a_push_is_running() {
+    let dir = repo_with_commits("auto_push_running", 1);
+    let key = project_key(&path_string(&dir));
+    AUTO_PUSH_RUNNING.lock().unwrap().get_or_insert_with(HashSet::new).insert(key.clone());
+
+    assert!(run_auto_push(&SystemGitRunner, path_string(&dir)).await.is_none());
+
+    AUTO_PUSH_RUNNING.lock().unwrap().as_mut().unwrap().remove(&key);
+    let status = run_auto_push(&SystemGitRunner, path_string(&dir)).await.unwrap();
+    assert!(!status.success);
+    assert!(status.error.unwrap().contains("git push"));
+}
+
+// ---------- 手动快照与防抖轮次 ----------
+
+fn pending_window(window: &Mutex<DebounceWindow>) {
+    let mut window = window.lock().unwrap();
+    window.active = true;
+    window.changed_paths.insert("file.txt".to_string());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn manual_snapshot_waits_for_auto_commit_and_cancels_window_only_on_success() {
+    let dir = repo_with_commits("manual_snapshot_interleaving", 1);
+    let project = path_string(&dir);
+    let (window, _stop_rx, _stop_tx) = register_test_session(&project);
+    let session = watcher_session(&project).unwrap();
+
+    // 自动提交正在执行：手动快照等待锁，不会提前取消防抖轮次
+    fs::write(dir.join("file.txt"), "auto").unwrap();
+    pending_window(&window);
+    let auto_commit = session.commit_lock.clone().lock_owned().await;
+    let manual = tokio::spawn(create_snapshot_with_options(system_git(), project.clone(), "手动".to_string(), SnapshotOptions::default(), None));
+    sleep(Duration::from_millis(100)).await;
+    assert!(!manual.is_finished());
+    assert!(window.lock().unwrap().active);
+    git(&dir, &["commit", "-qam", "auto"]);
+    drop(auto_commit);
+    let result = manual.await.unwrap().unwrap();
+    assert!(result.no_changes);
+    assert_eq!(window.lock().unwrap().cancel_count, 0);
+
+    // 提交失败时防抖轮次保留，自动提交照常进行
+    fs::write(dir.join("file.txt"), "manual").unwrap();
+    install_hook(&dir, "pre-commit", "#!/bin/sh\nexit 1\n");
+    let failed = create_snapshot_with_options(system_git(), project.clone(), "手动".to_string(), SnapshotOptions::default(), None).await.unwrap();
+    assert!(!failed.success);
+    assert!(window.lock().unwrap().active);
+    assert_eq!(window.lock().unwrap().cancel_count, 0);
+
+    fs::remove_file(dir.join(".git/hooks/pre-commit")).unwrap();
+    let succeeded = create_snapshot_with_options(system_git(), project.clone(), "手动".to_string(), SnapshotOptions::default(), None).await.unwrap();
+    assert!(succeeded.success, "{:?}", succeeded.error);
+    let window_state = window.lock().unwrap();
+    assert!(!window_state.active && window_state.changed_paths.is_empty());
+    assert_eq!(window_state.cancel_count, 1);
+    drop(window_state);
+    stop_watcher_sessions(Some(&project));
+}
+
+// ---------- 命令耗时记录 ----------
+
+// 最近一次命令耗时记录的结果；缓冲区由并行的测试共用，在命令返回后立即读取
+fn last_command_success(repo: &str, operation: &str) -> Option<bool> {
+    PERF_SAMPLES
+        .lock()
+        .unwrap()
+        .iter()
+        .rev()
+        .find(|sample| sample.kind == "command" && sample.operation == operation && sample.repo.as_deref() == Some(repo))
+        .map(|sample| sample.success)
+}
+
+#[tokio::test]
+async fn command_timer_records_failures() {
+    let dir = repo_with_commits("command_timer_outcome", 2);
+    let project = path_string(&dir);
+
+    let failed = rollback_to_commit(&SystemGitRunner, project.clone(), "0000000".to_string()).await.unwrap();
+    assert!(!failed.success);
+    assert_eq!(last_command_success(&project, "rollback"), Some(false));
+
+    let head = git(&dir, &["rev-parse", "HEAD~1"]).trim().to_string();
+    let rolled_back = rollback_to_commit(&SystemGitRunner, project.clone(), head).await.unwrap();
+    assert!(rolled_back.success, "{:?}", rolled_back.error);
+    assert_eq!(last_command_success(&project, "rollback"), Some(true));
+
+    let unchanged = create_snapshot_with_options(system_git(), project.clone(), "没有修改".to_string(), SnapshotOptions::default(), None).await.unwrap();
+    assert!(unchanged.no_changes);
+    assert_eq!(last_command_success(&project, "create_snapshot"), Some(false));
+}
+
+// ---------- 关联已有仓库时识别约定 ----------
+
+#[tokio::test]
+async fn ensure_git_repo_detects_existing_conventions() {
+    let remote = temp_project("existing_conventions_remote");
+    git(&remote, &["init", "-q", "--bare", "-b", "main"]);
+    let dir = init_repo("existing_conventions");
+    git(&dir, &["checkout", "-qb", "main"]);
+    for message in ["feat: add login", "fix: handle empty input", &format!("{}tweak styles", VIBE_COMMIT_PREFIX)] {
+        fs::write(dir.join("file.txt"), message).unwrap();
+        git(&dir, &["add", "."]);
+        git(&dir, &["commit", "-qm", message]);
+    }
+    git(&dir, &["remote", "add", "origin", &path_string(&remote)]);
+    git(&dir, &["push", "-q", "origin", "main"]);
+    git(&dir, &["remote", "set-head", "origin", "main"]);
+
+    let result = ensure_git_repo(path_string(&dir), None, None, None, None, None).await.unwrap();
+    assert!(result.success && !result.was_initialized);
+    let info = result.existing_repo.unwrap();
+    assert_eq!(info.default_branch.as_deref(), Some("main"));
+    assert!(info.uses_vibe_prefix);
+    assert_eq!(info.message_style, "conventional");
+    let identity = info.identity.unwrap();
+    assert_eq!((identity.name.as_str(), identity.email.as_str()), ("tester", "tester@example.com"));
+    assert_eq!(info.remotes, vec!["origin".to_string()]);
+}
+
+// ---------- 撤销最近一次 VibeSnap 操作 ----------
+
+#[tokio::test]
+async fn undo_last_vibesnap_operation_ignores_user_commits_and_resets() {
+    let dir = repo_with_commits("undo_vibesnap_operation", 2);
+    let project = path_string(&dir);
+    let before_snapshot = git(&dir, &["rev-parse", "HEAD"]).trim().to_string();
+    let first = git(&dir, &["rev-parse", "HEAD~1"]).trim().to_string();
+
+    fs::write(dir.join("file.txt"), "snapshot").unwrap();
+    let snapshot = create_snapshot_with_options(system_git(), project.clone(), "快照".to_string(), SnapshotOptions::default(), None)
+        .await
+        .unwrap();
+    assert!(snapshot.success);
+    let snapshot_hash = git(&dir, &["rev-parse", "HEAD"]).trim().to_string();
+    assert!(rollback_to_commit(&SystemGitRunner, project.clone(), first.clone()).await.unwrap().success);
+
+    // 用户自己的提交（即使带有 VibeSnap 前缀）和重置都不算 VibeSnap 操作
+    fs::write(dir.join("user.txt"), "user").unwrap();
+    git(&dir, &["add", "."]);
+    git(&dir, &["commit", "-qm", &format!("{}手动提交", VIBE_COMMIT_PREFIX)]);
+    git(&dir, &["reset", "-q", "--hard", "HEAD"]);
+    let reflog = git(&dir, &["reflog", "--format=%gs"]);
+    assert!(reflog.contains(VIBESNAP_SNAPSHOT_REFLOG_ACTION) && reflog.contains(VIBESNAP_ROLLBACK_REFLOG_ACTION));
+
+    let undo = undo_vibesnap_operation(project.clone()).await.unwrap();
+    assert!(undo.success, "{:?}", undo.error);
+    assert!(undo.message.contains("回退"), "{}", undo.message);
+    assert_eq!(git(&dir, &["rev-parse", "HEAD"]).trim(), snapshot_hash);
+
+    // 已撤销的回退被跳过，接着撤销快照提交
+    let undo = undo_vibesnap_operation(project.clone()).await.unwrap();
+    assert!(undo.success, "{:?}", undo.error);
+    assert!(undo.message.contains("快照"), "{}", undo.message);
+    assert_eq!(git(&dir, &["rev-parse", "HEAD"]).trim(), before_snapshot);
+
+    // 仓库自身的提交不可撤销
+    let undo = undo_vibesnap_operation(project).await.unwrap();
+    assert!(!undo.success);
+}
+
+// ---------- 需要处理的仓库状态 ----------
+
+#[tokio::test]
+async fn needs_attention_reports_merge_conflicts() {
+    let dir = repo_with_commits("needs_attention_conflict", 1);
+    let project = path_string(&dir);
+    assert!(needs_attention(project.clone()).await.unwrap().is_empty());
+
+    git(&dir, &["checkout", "-qb", "other"]);
+    fs::write(dir.join("file.txt"), "other").unwrap();
+    git(&dir, &["commit", "-qam", "other"]);
+    git(&dir, &["checkout", "-q", "master"]);
+    fs::write(dir.join("file.txt"), "master").unwrap();
+    git(&dir, &["commit", "-qam", "master"]);
+    git(&dir, &["merge", "-q", "other"]);
+
+    let items = needs_attention(project).await.unwrap();
+    let codes: Vec<&str> = items.iter().map(|item| item.code.as_str()).collect();
+    assert!(codes.contains(&"conflicts"), "{:?}", codes);
+    assert!(codes.contains(&"merge_in_progress"), "{:?}", codes);
+    assert!(!codes.contains(&"detached_head"));
+    let conflict = items.iter().find(|item| item.code == "conflicts").unwrap();
+    assert!(conflict.message.starts_with("1 个文件") && conflict.message.contains("file.txt"), "{}", conflict.message);
+}
+
+// ---------- 改写历史后的增量历史 ----------
+
+#[tokio::test]
+async fn history_since_reports_known_hash_missing_after_rewrite() {
+    let dir = repo_with_commits("history_since_rewrite", 1);
+    let project = path_string(&dir);
+    for message in ["one", "two"] {
+        fs::write(dir.join("file.txt"), message).unwrap();
+        git(&dir, &["commit", "-qam", &format!("{}{}", VIBE_COMMIT_PREFIX, message)]);
+    }
+    let known = git(&dir, &["rev-parse", "HEAD"]).trim().to_string();
+
+    let rewrite = rewrite_commit_messages(project.clone(), VIBE_COMMIT_PREFIX.to_string(), "[Snap] ".to_string(), None)
+        .await
+        .unwrap();
+    assert!(rewrite.success, "{:?}", rewrite.error);
+
+    let since = get_history_since(project, known).await.unwrap();
+    assert!(since.success && since.known_hash_missing && since.diverged);
+    let messages: Vec<&str> = since.history.iter().map(|item| item.message.as_str()).collect();
+    assert_eq!(messages, vec!["[Snap] two", "[Snap] one"]);
+}
+
+// ---------- 改写提交信息 ----------
+
+#[tokio::test]
+async fn rewrite_messages_carries_over_tags_and_notes() {
+    let dir = repo_with_commits("rewrite_messages_tags_notes", 1);
+    let project = path_string(&dir);
+    for message in ["one", "two"] {
+        fs::write(dir.join("file.txt"), message).unwrap();
+        git(&dir, &["commit", "-qam", &format!("{}{}", VIBE_COMMIT_PREFIX, message)]);
+    }
+    git(&dir, &["tag", "light", "HEAD~1"]);
+    git(&dir, &["tag", "-a", "-m", "release", "annotated", "HEAD"]);
+    git(&dir, &["notes", "add", "-m", "review ok", "HEAD~1"]);
+    git(&dir, &["notes", "--ref", "refs/notes/vibesnap", "add", "-m", "prompt", "HEAD"]);
+
+    let result = rewrite_commit_messages(project, VIBE_COMMIT_PREFIX.to_string(), "[Snap] ".to_string(), None)
+        .await
+        .unwrap();
+    assert!(result.success, "{:?}", result.error);
+    assert_eq!(result.rewritten_tags, vec!["annotated".to_string(), "light".to_string()]);
+    assert_eq!(result.copied_notes, 2);
+    assert!(result.message.contains(&result.backup_branches[0]), "{}", result.message);
+
+    assert_eq!(git(&dir, &["log", "-1", "--format=%s", "light"]).trim(), "[Snap] one");
+    assert_eq!(git(&dir, &["log", "-1", "--format=%s", "annotated"]).trim(), "[Snap] two");
+    assert_eq!(git(&dir, &["cat-file", "-t", "annotated"]).trim(), "tag");
+    assert_eq!(git(&dir, &["notes", "show", "HEAD~1"]).trim(), "review ok");
+    assert_eq!(git(&dir, &["notes", "--ref", "refs/notes/vibesnap", "show", "HEAD"]).trim(), "prompt");
+}
+
+// ---------- 监听数耗尽时的轮询降级 ----------
+
+#[test]
+fn polling_fallback_skips_heavy_directories() {
+    assert!(matches!(polling_mode(Path::new("/project/src")), Some(RecursiveMode::Recursive)));
+    assert!(matches!(polling_mode(Path::new("/project/.git")), Some(RecursiveMode::NonRecursive)));
+    assert!(polling_mode(Path::new("/project/node_modules")).is_none());
+    assert!(polling_mode(Path::new("/project/target")).is_none());
+}
+
+// ---------- 两个快照中的文件内容 ----------
+
+#[tokio::test]
+async fn file_versions_reads_both_revisions() {
+    let dir = repo_with_commits("file_versions", 2);
+    let project = path_string(&dir);
+    fs::write(dir.join("image.bin"), b"a\0b").unwrap();
+    git(&dir, &["add", "."]);
+    git(&dir, &["commit", "-qm", "add binary"]);
+
+    let versions = get_file_versions(project.clone(), "HEAD~2".to_string(), "HEAD~1".to_string(), "file.txt".to_string())
+        .await
+        .unwrap();
+    assert!(versions.success && versions.found_a && versions.found_b);
+    assert_eq!(versions.content_a.as_deref(), Some("v1"));
+    assert_eq!(versions.content_b.as_deref(), Some("v2"));
+
+    // 文件只存在于其中一个快照
+    let versions = get_file_versions(project.clone(), "HEAD~1".to_string(), "HEAD".to_string(), "image.bin".to_string())
+        .await
+        .unwrap();
+    assert!(versions.success && !versions.found_a && versions.found_b);
+    assert!(versions.binary_b && versions.content_b.is_none());
+
+    let versions = get_file_versions(project, "HEAD".to_string(), "missing".to_string(), "file.txt".to_string())
+        .await
+        .unwrap();
+    assert!(!versions.success && versions.error.is_some());
+}
+
+// ---------- 编码检查 ----------
+
+#[test]
+fn encoding_check_flags_only_utf16_and_utf32_boms() {
+    let dir = temp_project("encoding_check_boms");
+    let check = |name: &str, content: &[u8]| {
+        fs::write(dir.join(name), content).unwrap();
+        detect_encoding_issue(&dir.join(name))
+    };
+    assert_eq!(check("plain.txt", "你好\n".as_bytes()), None);
+    assert_eq!(check("utf8_bom.txt", b"\xEF\xBB\xBFhello\n"), None);
+    assert_eq!(check("utf16le.txt", b"\xFF\xFEh\x00i\x00"), Some("bom"));
+    assert_eq!(check("utf16be.txt", b"\xFE\xFF\x00h\x00i"), Some("bom"));
+    assert_eq!(check("utf32be.txt", b"\x00\x00\xFE\xFF\x00\x00\x00h"), Some("bom"));
+    assert_eq!(check("nul.txt", b"a\x00b"), Some("null_bytes"));
+    assert_eq!(check("latin1.txt", b"caf\xE9 au lait"), Some("invalid_utf8"));
+}
+
+// ---------- 初始化时统一换行符 ----------
+
+#[tokio::test]
+async fn ensure_git_repo_commits_gitattributes_when_normalizing_line_endings() {
+    let dir = temp_project("normalize_line_endings");
+    fs::write(dir.join("readme.md"), "hello\r\n").unwrap();
+    let result = ensure_git_repo(path_string(&dir), None, None, Some(true), None, None).await.unwrap();
+    assert!(result.success && result.was_initialized, "{:?}", result.error);
+    assert_eq!(fs::read_to_string(dir.join(".gitattributes")).unwrap(), DEFAULT_GITATTRIBUTES);
+    let committed = git(&dir, &["ls-tree", "--name-only", "HEAD"]);
+    assert!(committed.lines().any(|name| name == ".gitattributes"), "{}", committed);
+
+    // 已有的 .gitattributes 保持原样
+    let existing = temp_project("normalize_line_endings_existing");
+    fs::write(existing.join(".gitattributes"), "*.sh text eol=lf\n").unwrap();
+    let result = ensure_git_repo(path_string(&existing), None, None, Some(true), None, None).await.unwrap();
+    assert!(result.success, "{:?}", result.error);
+    assert_eq!(fs::read_to_string(existing.join(".gitattributes")).unwrap(), "*.sh text eol=lf\n");
+}
+
+// ---------- git 调用审计记录 ----------
+
+#[tokio::test]
+async fn git_audit_log_is_keyed_by_project_and_tracks_commands_across_threads() {
+    let dir = repo_with_commits("git_audit_log", 2);
+    let project = path_string(&dir);
+    assert!(rollback_to_commit(&SystemGitRunner, project.clone(), "HEAD~1".to_string()).await.unwrap().success);
+
+    // 在其他线程上执行的 git 调用也归到正在执行的命令
+    let timer = CommandTimer::start("audit_test", &project);
+    let other_thread = project.clone();
+    tokio::task::spawn_blocking(move || run_git(Command::new("git").arg("status").current_dir(&other_thread)))
+        .await
+        .unwrap()
+        .unwrap();
+    drop(timer);
+    run_git(Command::new("git").arg("log").current_dir(&dir)).unwrap();
+
+    // 用不同写法的路径查询同一个项目
+    let log = get_git_audit_log(format!("{}/.", project), None).await.unwrap();
+    let triggered_by = |subcommand: &str| {
+        log.iter()
+            .rev()
+            .find(|entry| entry.args.iter().any(|arg| arg == subcommand))
+            .map(|entry| entry.triggered_by.clone())
+    };
+    assert_eq!(triggered_by("reset"), Some(Some("rollback".to_string())));
+    assert_eq!(triggered_by("status"), Some(Some("audit_test".to_string())));
+    assert_eq!(triggered_by("log"), Some(None));
+}
+
+// ---------- 拉取远程 ----------
+
+#[tokio::test]
+async fn fetch_from_bare_remote_streams_progress_and_records_perf_sample() {
+    let remote = temp_project("fetch_remote_bare");
+    git(&remote, &["init", "-q", "--bare", "-b", "master"]);
+    let upstream = repo_with_commits("fetch_remote_upstream", 2);
+    git(&upstream, &["push", "-q", &path_string(&remote), "master"]);
+    let dir = repo_with_commits("fetch_remote_local", 1);
+    let project = path_string(&dir);
+    git(&dir, &["remote", "add", "origin", &path_string(&remote)]);
+
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let collected = lines.clone();
+    let result = fetch_from_remote(project.clone(), None, move |progress| {
+        collected.lock().unwrap().push(progress.line);
+    })
+    .await
+    .unwrap();
+    assert!(result.success, "{:?}", result.error);
+    assert_eq!(result.remote, "origin");
+    let upstream_head = git(&upstream, &["rev-parse", "HEAD"]).trim().to_string();
+    assert!(result
+        .updated_refs
+        .iter()
+        .any(|fetched| fetched.name == "refs/remotes/origin/master" && fetched.new_hash.as_deref() == Some(upstream_head.as_str())));
+    assert!(lines.lock().unwrap().iter().any(|line| line.contains("origin/master")), "{:?}", lines.lock().unwrap());
+
+    let fetched = PERF_SAMPLES
+        .lock()
+        .unwrap()
+        .iter()
+        .rev()
+        .find(|sample| sample.operation == "git fetch" && sample.repo.as_deref() == Some(project.as_str()))
+        .map(|sample| sample.success);
+    assert_eq!(fetched, Some(true));
+}
+
+// ---------- 会话内的增量差异 ----------
+
+#[tokio::test]
+async fn incremental_diff_finds_session_context_beyond_first_page() {
+    let dir = init_repo("incremental_beyond_page");
+    fs::write(dir.join("base.txt"), "base").unwrap();
+    git(&dir, &["add", "."]);
+    commit_at(&dir, "base", 1_000_000);
+
+    // 按提交时间排序时，侧分支的提交排在 main.txt 提交之前
+    git(&dir, &["checkout", "-qb", "side"]);
+    fs::write(dir.join("side.txt"), "side").unwrap();
+    git(&dir, &["add", "."]);
+    commit_at(&dir, "side", 1_000_060);
+    git(&dir, &["checkout", "-q", "master"]);
+    fs::write(dir.join("main.txt"), "main").unwrap();
+    git(&dir, &["add", "."]);
+    commit_at(&dir, "main", 1_000_120);
+    let target = git(&dir, &["rev-parse", "HEAD"]).trim().to_string();
+    git(&dir, &["merge", "-q", "--no-edit", "side"]);
+
+    // 让目标提交落在历史记录第一页之外
+    for i in 0..DEFAULT_HISTORY_LIMIT {
+        fs::write(dir.join("base.txt"), format!("v{}", i)).unwrap();
+        commit_at(&dir, &format!("later {}", i), 2_000_000 + i as i64);
+    }
+
+    let diff = get_incremental_diff(path_string(&dir), target, Some(600.0)).await.unwrap();
+    assert!(diff.success, "{:?}", diff.error);
+    // 与会话中的上一个快照（侧分支提交）比较，而不是与父提交比较
+    assert!(diff.files.contains(&"side.txt".to_string()), "{:?}", diff.files);
+    assert!(diff.files.contains(&"main.txt".to_string()), "{:?}", diff.files);
+}
+
+// ---------- 工作区管理 ----------
+
+#[tokio::test]
+async fn worktrees_are_listed_removed_and_pruned() {
+    let dir = repo_with_commits("worktrees_main", 2);
+    let project = path_string(&dir);
+    let parent = fs::canonicalize(temp_project("worktrees_linked")).unwrap();
+    let snapshot = path_string(&parent.join("snapshot"));
+    let side = path_string(&parent.join("side"));
+    git(&dir, &["worktree", "add", "-q", "--detach", &snapshot, "HEAD~1"]);
+    git(&dir, &["worktree", "add", "-q", "-b", "side", &side]);
+
+    let worktrees = list_worktrees(project.clone()).await.unwrap();
+    assert_eq!(worktrees.len(), 3);
+    assert!(worktrees[0].is_main && !worktrees[0].detached);
+    assert_eq!(worktrees[0].branch.as_deref(), Some("master"));
+    let checked_out = worktrees.iter().find(|worktree| worktree.path == snapshot).unwrap();
+    assert!(checked_out.detached && checked_out.branch.is_none() && !checked_out.is_main);
+    assert_eq!(checked_out.head.as_deref(), Some(git(&dir, &["rev-parse", "HEAD~1"]).trim()));
+    assert_eq!(worktrees.iter().find(|worktree| worktree.path == side).unwrap().branch.as_deref(), Some("side"));
+
+    // 主工作区不能移除
+    assert!(!remove_worktree(project.clone(), project.clone(), None).await.unwrap().success);
+    let removed = remove_worktree(project.clone(), snapshot.clone(), None).await.unwrap();
+    assert!(removed.success, "{:?}", removed.error);
+    assert!(!Path::new(&snapshot).exists());
+
+    fs::remove_dir_all(&side).unwrap();
+    let pruned = prune_worktrees(project.clone()).await.unwrap();
+    assert!(pruned.success, "{:?}", pruned.error);
+    assert_eq!(pruned.removed, vec![side]);
+    assert_eq!(list_worktrees(project).await.unwrap().len(), 1);
+}
+
+// ---------- 按差异块提交 ----------
+
+fn hunk_snapshot_options(selection: Vec<HunkSelection>) -> SnapshotOptions {
+    SnapshotOptions {
+        hunk_selection: Some(selection),
+        ..SnapshotOptions::default()
+    }
+}
+
+#[tokio::test]
+async fn snapshot_commits_only_selected_hunks() {
+    let dir = init_repo("snapshot_selected_hunks");
+    let project = path_string(&dir);
+    let original: Vec<String> = (1..=30).map(|i| format!("line {}", i)).collect();
+    fs::write(dir.join("file.txt"), original.join("\n") + "\n").unwrap();
+    git(&dir, &["add", "."]);
+    git(&dir, &["commit", "-qm", "base"]);
+
+    // 三处相距较远的修改：插入两行、AI 的修改、自己未完成的实验
+    let mut edited = original.clone();
+    edited[27] = "line 28 experiment".to_string();
+    edited[14] = "line 15 ai".to_string();
+    edited.insert(2, "inserted a".to_string());
+    edited.insert(3, "inserted b".to_string());
+    let edited_text = edited.join("\n") + "\n";
+    fs::write(dir.join("file.txt"), &edited_text).unwrap();
+
+    let hunks = get_file_hunks(project.clone(), "file.txt".to_string()).await.unwrap();
+    assert!(hunks.success, "{:?}", hunks.error);
+    assert_eq!(hunks.hunks.len(), 3);
+    let selection = vec![HunkSelection {
+        file_path: "file.txt".to_string(),
+        include_hunk_ids: vec![hunks.hunks[0].id.clone(), hunks.hunks[1].id.clone()],
+    }];
+    let result = create_snapshot_with_options(system_git(), project.clone(), "部分提交".to_string(), hunk_snapshot_options(selection), None)
+        .await
+        .unwrap();
+    assert!(result.success, "{:?}", result.error);
+
+    let mut expected = original.clone();
+    expected[14] = "line 15 ai".to_string();
+    expected.insert(2, "inserted a".to_string());
+    expected.insert(3, "inserted b".to_string());
+    assert_eq!(git(&dir, &["show", "HEAD:file.txt"]), expected.join("\n") + "\n");
+    // 工作区不变，未选中的差异块仍然存在
+    assert_eq!(fs::read_to_string(dir.join("file.txt")).unwrap(), edited_text);
+    let remaining = get_file_hunks(project.clone(), "file.txt".to_string()).await.unwrap();
+    assert_eq!(remaining.hunks.len(), 1);
+    assert!(remaining.hunks[0].lines.iter().any(|line| line == "+line 28 experiment"));
+
+    // 已经提交过的差异块 ID 不再有效
+    let stale = vec![HunkSelection {
+        file_path: "file.txt".to_string(),
+        include_hunk_ids: vec![hunks.hunks[0].id.clone()],
+    }];
+    let result = create_snapshot_with_options(system_git(), project, "过期".to_string(), hunk_snapshot_options(stale), None)
+        .await
+        .unwrap();
+    assert!(!result.success);
+    assert!(result.error.unwrap().contains("重新获取差异块"));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn failing_hook_does_not_commit_unselected_hunks() {
+    let dir = init_repo("snapshot_selected_hunks_hook");
+    let project = path_string(&dir);
+    let original: Vec<String> = (1..=30).map(|i| format!("line {}", i)).collect();
+    fs::write(dir.join("file.txt"), original.join("\n") + "\n").unwrap();
+    git(&dir, &["add", "."]);
+    git(&dir, &["commit", "-qm", "base"]);
+    let head = git(&dir, &["rev-parse", "HEAD"]);
+    install_hook(&dir, "pre-commit", FAIL_ONCE_HOOK);
+
+    let mut edited = original.clone();
+    edited[2] = "line 3 ai".to_string();
+    edited[27] = "line 28 experiment".to_string();
+    let edited_text = edited.join("\n") + "\n";
+    fs::write(dir.join("file.txt"), &edited_text).unwrap();
+
+    let hunks = get_file_hunks(project.clone(), "file.txt".to_string()).await.unwrap();
+    assert_eq!(hunks.hunks.len(), 2);
+    let selection = vec![HunkSelection {
+        file_path: "file.txt".to_string(),
+        include_hunk_ids: vec![hunks.hunks[0].id.clone()],
+    }];
+    let result = create_snapshot_with_options(system_git(), project.clone(), "部分提交".to_string(), hunk_snapshot_options(selection), None)
+        .await
+        .unwrap();
+
+    // 钩子没有改写文件，不应重新暂存整个文件后重试
+    assert!(!result.success);
+    assert_eq!(git(&dir, &["rev-parse", "HEAD"]), head);
+    assert!(!git(&dir, &["log", "--all", "-p"]).contains("line 28 experiment"));
+    assert_eq!(fs::read_to_string(dir.join("file.txt")).unwrap(), edited_text);
+}
+
+// ---------- 最近修改的文件 ----------
+
+#[tokio::test]
+async fn recently_changed_files_ranks_files_by_touch_count() {
+    let dir = init_repo("recently_changed_files");
+    let project = path_string(&dir);
+    assert!(recently_changed_files(project.clone(), 5).await.unwrap().is_empty());
+    for (i, files) in [vec!["hot.rs", "a.rs"], vec!["hot.rs"], vec!["b.rs", "hot.rs"], vec!["c.rs", "b.rs"]].iter().enumerate() {
+        for file in files {
+            fs::write(dir.join(file), i.to_string()).unwrap();
+        }
+        git(&dir, &["add", "."]);
+        git(&dir, &["commit", "-qm", &format!("commit {}", i)]);
+    }
+
+    // 次数相同时最近修改的文件在前
+    let recent = recently_changed_files(project.clone(), 3).await.unwrap();
+    let ranked: Vec<(&str, usize)> = recent.iter().map(|file| (file.path.as_str(), file.touch_count)).collect();
+    assert_eq!(ranked, vec![("b.rs", 2), ("hot.rs", 2), ("c.rs", 1)]);
+
+    // 提交数少于请求的数量
+    let recent = recently_changed_files(project, 100).await.unwrap();
+    assert_eq!(recent.len(), 4);
+    assert_eq!((recent[0].path.as_str(), recent[0].touch_count), ("hot.rs", 3));
+}
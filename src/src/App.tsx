@@ -23,6 +23,13 @@ function App() {
     } catch (error) {
       console.error('Git 初始化失败:', error)
     }
+
+    // 记录到最近项目（同时生成仓库标识），文件夹被移动后用于查找和校验新路径
+    try {
+      await invoke('add_recent_project', { projectPath: path })
+    } catch (error) {
+      console.error('记录最近项目失败:', error)
+    }
   }
 
   const handleSnapshotCreate = (success: boolean, message: string) => {
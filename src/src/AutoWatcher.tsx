@@ -85,7 +85,7 @@ export const AutoWatcher: React.FC<AutoWatcherProps> = ({
 
   const handleStopWatching = async () => {
     try {
-      const result = await invoke<any>('stop_file_watcher');
+      const result = await invoke<any>('stop_file_watcher', { projectPath });
       setIsWatching(false);
       setWatcherStatus(result);
       onAutoCommit(true, '自动监听已停止');
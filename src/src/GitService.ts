@@ -12,10 +12,24 @@ export interface GitStatusInfo {
   error?: string;
 }
 
+export interface GitStatusStructured {
+  modified: string[];
+  staged: string[];
+  untracked: string[];
+  deleted: string[];
+  conflicted: string[];
+}
+
+export interface EmptyRepository {
+  branch: string;
+  pending_files: number;
+}
+
 export interface GitInfo {
   branch: string;
   commit: string;
   error?: string;
+  empty_repository?: EmptyRepository; // 仓库还没有任何提交
 }
 
 export class GitService {
@@ -54,6 +68,15 @@ export class GitService {
     }
   }
 
+  /**
+   * 获取按状态分类的文件列表 - 由 Rust 后端解析 porcelain 状态码
+   */
+  async getStructuredStatus(): Promise<GitStatusStructured> {
+    return invoke<GitStatusStructured>('git_status_structured', {
+      projectPath: this.repoPath
+    });
+  }
+
   /**
    * 获取 Git 信息 - 使用 Rust 后端
    */
@@ -12,16 +12,32 @@ interface SnapshotDetailProps {
   onRollback: (success: boolean, message: string) => void;
 }
 
+interface SnapshotFileChange {
+  path: string;
+  change_type: string; // "added", "modified", "deleted", "renamed", "copied", "type_changed"
+  old_path?: string;
+  submodule?: SubmoduleDiff | null;
+}
+
+interface SubmoduleDiff {
+  old_commit: string; // 新增子模块时为空
+  new_commit: string; // 删除子模块时为空
+}
+
 interface SnapshotDiff {
   success: boolean;
   files: string[];
+  changes: SnapshotFileChange[];
   error?: string;
 }
 
 interface FriendlyDiffLine {
   content: string;
   change_type: string; // "added", "removed", "unchanged"
-  line_number?: number;
+  line_number?: number; // 已弃用，请使用 old_line_number / new_line_number
+  old_line_number?: number;
+  new_line_number?: number;
+  hunk_range?: string; // 例如 "@@ -1,4 +1,5 @@"
 }
 
 interface FriendlyDiffContent {
@@ -3,15 +3,27 @@ import { invoke } from '@tauri-apps/api/core';
 import { ConfirmDialog } from './ConfirmDialog';
 
 interface SnapshotHistoryItem {
-  hash: string;
+  short_hash: string;
+  full_hash: string;
+  hash: string; // 已弃用，等同于 short_hash
   date: string;
+  raw_date: string;
   message: string;
+  tags: string[];
+  has_annotation: boolean;
+}
+
+interface EmptyRepository {
+  branch: string;
+  pending_files: number;
 }
 
 interface SnapshotHistory {
   success: boolean;
   history: SnapshotHistoryItem[];
   error?: string;
+  empty_repository?: EmptyRepository; // 仓库还没有任何提交
+  total_count: number; // 提交总数，用于分页
 }
 
 interface SnapshotTimelineProps {